@@ -7,9 +7,9 @@ use crypto::HashValue;
 use pprof::criterion::{Output, PProfProfiler};
 use starcoin_accumulator::{accumulator_info::AccumulatorInfo, Accumulator, MerkleAccumulator};
 use starcoin_config::RocksdbConfig;
-use starcoin_storage::cache_storage::CacheStorage;
+use starcoin_storage::cache_storage::{CacheStorage, ComposedKey};
 use starcoin_storage::db_storage::DBStorage;
-use starcoin_storage::storage::StorageInstance;
+use starcoin_storage::storage::{InnerStore, StorageInstance};
 use starcoin_storage::Storage;
 use std::sync::Arc;
 
@@ -30,6 +30,58 @@ fn storage_transaction(c: &mut Criterion) {
     });
 }
 
+/// read the same keys in a tight loop, compare the allocation of composing keys.
+fn cache_read_loop(c: &mut Criterion) {
+    let cache = CacheStorage::new(None);
+    let keys: Vec<Vec<u8>> = (0..1000u64).map(|i| i.to_be_bytes().to_vec()).collect();
+    for key in &keys {
+        cache.put("default", key.clone(), vec![0u8; 32]).unwrap();
+    }
+    let composed_keys: Vec<ComposedKey> = keys
+        .iter()
+        .map(|key| ComposedKey::new("default", key.clone()))
+        .collect();
+    let mut group = c.benchmark_group("cache_read_loop");
+    group.bench_function("contains_key_and_get", |b| {
+        b.iter(|| {
+            for key in &keys {
+                if cache.contains_key("default", key.clone()).unwrap() {
+                    cache.get("default", key.clone()).unwrap();
+                }
+            }
+        })
+    });
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            for key in &keys {
+                cache.get("default", key.clone()).unwrap();
+            }
+        })
+    });
+    group.bench_function("put", |b| {
+        b.iter(|| {
+            for key in &keys {
+                cache.put("default", key.clone(), vec![0u8; 32]).unwrap();
+            }
+        })
+    });
+    group.bench_function("get_if_present", |b| {
+        b.iter(|| {
+            for key in &keys {
+                cache.get_if_present("default", key.clone()).unwrap();
+            }
+        })
+    });
+    group.bench_function("get_composed", |b| {
+        b.iter(|| {
+            for key in &composed_keys {
+                cache.get_composed(key);
+            }
+        })
+    });
+    group.finish();
+}
+
 /// accumulator benchmarks
 fn accumulator_append(c: &mut Criterion) {
     ::logger::init_for_test();
@@ -68,12 +120,13 @@ criterion_group!(
     name=starcoin_storage_benches;
     config = Criterion::default()
     .with_profiler(PProfProfiler::new(100, Output::Flamegraph(None)));
-    targets=storage_transaction, accumulator_append
+    targets=storage_transaction, accumulator_append, cache_read_loop
 );
 #[cfg(not(target_os = "linux"))]
 criterion_group!(
     starcoin_storage_benches,
     storage_transaction,
-    accumulator_append
+    accumulator_append,
+    cache_read_loop
 );
 criterion_main!(starcoin_storage_benches);
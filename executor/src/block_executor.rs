@@ -1,14 +1,16 @@
 // Copyright (c) The Starcoin Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::Result;
 use starcoin_crypto::HashValue;
 use starcoin_state_api::ChainState;
 use starcoin_types::error::BlockExecutorError;
 use starcoin_types::error::ExecutorResult;
 use starcoin_types::transaction::TransactionStatus;
-use starcoin_types::transaction::{Transaction, TransactionInfo};
+use starcoin_types::transaction::{Transaction, TransactionInfo, TransactionOutput};
 use starcoin_vm_types::contract_event::ContractEvent;
 use vm_runtime::metrics::VMMetrics;
+use vm_runtime::starcoin_vm::StarcoinVM;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct BlockExecutedData {
@@ -84,3 +86,45 @@ pub fn block_execute(
     executed_data.state_root = chain_state.state_root();
     Ok(executed_data)
 }
+
+/// Execute the block transactions one by one with gas_limit, the write set of every kept txn
+/// is applied to the `chain_state` before the next txn is executed, so the later txns read the
+/// effects of the prior ones from the `chain_state` itself, as a real block commit proceeds.
+/// The discarded txns are returned without applying anything, and if gas is used up when
+/// executing some txn, only the outputs of the previous txns are returned, the same as
+/// [`crate::execute_block_transactions`].
+/// Every txn is executed by the vm as a block of its own on purpose: a multi txn block reads
+/// the prior writes from the vm data cache instead of the `chain_state`. So the vm configs are
+/// loaded for every txn rather than once per block, the cost of reading from the `chain_state`.
+/// The `chain_state` is neither committed nor flushed, it is left to the caller. It is taken as
+/// `&dyn ChainState` rather than `&mut dyn ChainStateWriter`, because `apply_write_set` only
+/// needs `&self`.
+pub fn execute_and_apply_block(
+    chain_state: &dyn ChainState,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<TransactionOutput>> {
+    let mut vm = StarcoinVM::new(metrics);
+    let mut gas_left = block_gas_limit;
+    let mut outputs = Vec::with_capacity(txns.len());
+    for txn in txns {
+        let output = match vm
+            .execute_block_transactions(chain_state.as_super(), vec![txn], Some(gas_left))?
+            .pop()
+        {
+            Some((_, output)) => output,
+            // the gas is used up.
+            None => break,
+        };
+        match gas_left.checked_sub(output.gas_used()) {
+            Some(l) => gas_left = l,
+            None => break,
+        }
+        if let TransactionStatus::Keep(_) = output.status() {
+            chain_state.apply_write_set(output.write_set().clone())?;
+        }
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
@@ -1,20 +1,123 @@
 // Copyright (c) The Starcoin Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::Result;
-use starcoin_types::transaction::{SignedUserTransaction, Transaction, TransactionOutput};
+use crate::vm_pool::VmPool;
+use anyhow::{ensure, format_err, Result};
+use parking_lot::{Mutex, MutexGuard};
+use starcoin_types::block_metadata::BlockMetadata;
+use starcoin_types::transaction::{
+    SignedUserTransaction, Transaction, TransactionOutput, TransactionStatus,
+};
+use starcoin_vm_types::access_path::AccessPath;
+use starcoin_vm_types::contract_event::ContractEvent;
+use starcoin_vm_types::gas_schedule::CostTable;
 use starcoin_vm_types::identifier::Identifier;
 use starcoin_vm_types::language_storage::{ModuleId, TypeTag};
-use starcoin_vm_types::{state_view::StateView, vm_status::VMStatus};
+use starcoin_vm_types::{
+    state_view::StateView,
+    vm_status::{StatusCode, VMStatus},
+};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Instant;
 use vm_runtime::metrics::VMMetrics;
 use vm_runtime::starcoin_vm::StarcoinVM;
+use vm_status_translator::{explain_vm_status, VmStatusExplainView};
+
+/// The failure of a txn or a function explained for debugging: the module, function name and
+/// code offset of the frame where the execution failed, or the explained abort code.
+/// It is only the failed location the vm status reports, the vm records neither the call stack
+/// nor the executed instructions.
+pub type FailureExplanation = VmStatusExplainView;
 
 pub fn execute_transactions(
     chain_state: &dyn StateView,
     txns: Vec<Transaction>,
     metrics: Option<VMMetrics>,
 ) -> Result<Vec<TransactionOutput>> {
-    do_execute_block_transactions(chain_state, txns, None, metrics)
+    do_execute_block_transactions(chain_state, txns, None, None, metrics).map(without_gas)
+}
+
+/// Same as [`execute_transactions`], but also return the events of every output, e.g. to index
+/// the events. The events of a txn are in the order they are emitted.
+pub fn execute_transactions_with_events(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<(TransactionOutput, Vec<ContractEvent>)>> {
+    Ok(execute_transactions(chain_state, txns, metrics)?
+        .into_iter()
+        .map(|output| {
+            let events = output.events().to_vec();
+            (output, events)
+        })
+        .collect())
+}
+
+/// Execute a single `txn`, same as [`execute_transactions`] with a one txn vec,
+/// see [`simulate_transaction`] to execute with a shared vm.
+pub fn execute_transaction(
+    chain_state: &dyn StateView,
+    txn: Transaction,
+    metrics: Option<VMMetrics>,
+) -> Result<TransactionOutput> {
+    execute_transactions(chain_state, vec![txn], metrics)?
+        .pop()
+        .ok_or_else(|| format_err!("Txn output must exist."))
+}
+
+/// Execute the user `txns` in a block with the given `block_metadata`, so the txns see the
+/// block timestamp, author and number of the metadata, used for simulation.
+/// Only the outputs of the user txns are returned, the block metadata txn output is excluded,
+/// so the outputs are aligned 1:1 with `txns`, the i-th output is the output of the i-th txn.
+/// Return error if the block metadata txn is discarded.
+pub fn execute_transactions_with_metadata(
+    chain_state: &dyn StateView,
+    block_metadata: BlockMetadata,
+    txns: Vec<SignedUserTransaction>,
+    vm: &Arc<Mutex<StarcoinVM>>,
+) -> Result<Vec<TransactionOutput>> {
+    execute_transactions_with_metadata_with_locked_vm(
+        chain_state,
+        block_metadata,
+        txns,
+        &mut lock_vm(vm, "execute_transactions_with_metadata"),
+    )
+}
+
+/// Same as [`execute_transactions_with_metadata`], with a `vm` the caller has already locked.
+pub fn execute_transactions_with_metadata_with_locked_vm(
+    chain_state: &dyn StateView,
+    block_metadata: BlockMetadata,
+    txns: Vec<SignedUserTransaction>,
+    vm: &mut StarcoinVM,
+) -> Result<Vec<TransactionOutput>> {
+    let txn_count = txns.len();
+    let txns = std::iter::once(Transaction::BlockMetadata(block_metadata))
+        .chain(txns.into_iter().map(Transaction::UserTransaction))
+        .collect();
+    let mut outputs = vm
+        .execute_block_transactions(chain_state, txns, None)?
+        .into_iter()
+        .map(|(_, output)| output);
+    let block_metadata_output = outputs
+        .next()
+        .ok_or_else(|| format_err!("Block metadata txn output must exist."))?;
+    ensure!(
+        matches!(block_metadata_output.status(), TransactionStatus::Keep(_)),
+        "Block metadata txn is discarded: {:?}",
+        block_metadata_output.status()
+    );
+    let outputs: Vec<TransactionOutput> = outputs.collect();
+    ensure!(
+        outputs.len() == txn_count,
+        "Expect {} user txn outputs, but got {}",
+        txn_count,
+        outputs.len()
+    );
+    Ok(outputs)
 }
 
 /// Execute a block transactions with gas_limit,
@@ -25,36 +128,374 @@ pub fn execute_block_transactions(
     block_gas_limit: u64,
     metrics: Option<VMMetrics>,
 ) -> Result<Vec<TransactionOutput>> {
-    do_execute_block_transactions(chain_state, txns, Some(block_gas_limit), metrics)
+    execute_block_transactions_with_gas(chain_state, txns, block_gas_limit, metrics)
+        .map(without_gas)
+}
+
+/// Same as [`execute_block_transactions`], but return the gas used by each txn along with its output.
+pub fn execute_block_transactions_with_gas(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<(u64, TransactionOutput)>> {
+    do_execute_block_transactions(chain_state, txns, Some(block_gas_limit), None, metrics)
+}
+
+/// Same as [`execute_block_transactions`], but also return the gas used by the whole block,
+/// so a block builder knows how close the block is to the `block_gas_limit` when the gas is
+/// used up and the outputs are truncated, and whether to include more txns.
+pub fn execute_block_transactions_with_gas_used(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<(Vec<TransactionOutput>, u64)> {
+    let outputs = execute_block_transactions_with_gas(chain_state, txns, block_gas_limit, metrics)?;
+    let gas_used = outputs.iter().map(|(gas_used, _)| gas_used).sum();
+    Ok((without_gas(outputs), gas_used))
+}
+
+/// Same as [`execute_block_transactions_with_gas`], but also return the executed txn,
+/// the txns not executed because of the gas limit are not included.
+pub fn execute_block_transactions_with_txn(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<(Transaction, u64, TransactionOutput)>> {
+    let outputs = do_execute_block_transactions(
+        chain_state,
+        txns.clone(),
+        Some(block_gas_limit),
+        None,
+        metrics,
+    )?;
+    Ok(txns
+        .into_iter()
+        .zip(outputs)
+        .map(|(txn, (gas_used, output))| (txn, gas_used, output))
+        .collect())
+}
+
+/// Same as [`execute_block_transactions`], but execute with the `gas_schedule` instead of
+/// the on-chain gas schedule if it is set.
+pub fn execute_block_transactions_with_gas_schedule(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    gas_schedule: Option<CostTable>,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<TransactionOutput>> {
+    do_execute_block_transactions(
+        chain_state,
+        txns,
+        Some(block_gas_limit),
+        gas_schedule,
+        metrics,
+    )
+    .map(without_gas)
+}
+
+/// Same as [`execute_block_transactions`] with the shared `vm`, but also stop before the next
+/// user txn once the `deadline` passed, the outputs of the txns executed before are returned.
+/// The deadline is checked between txns, a running txn is not interrupted.
+/// Return whether the execution is stopped by the deadline together with the outputs.
+pub fn execute_block_transactions_with_deadline(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    deadline: Instant,
+    vm: &Arc<Mutex<StarcoinVM>>,
+) -> Result<(Vec<TransactionOutput>, bool)> {
+    execute_block_transactions_with_deadline_with_locked_vm(
+        chain_state,
+        txns,
+        block_gas_limit,
+        deadline,
+        &mut lock_vm(vm, "execute_block_transactions_with_deadline"),
+    )
+}
+
+/// Same as [`execute_block_transactions_with_deadline`], with a `vm` the caller has already locked.
+pub fn execute_block_transactions_with_deadline_with_locked_vm(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    deadline: Instant,
+    vm: &mut StarcoinVM,
+) -> Result<(Vec<TransactionOutput>, bool)> {
+    let (outputs, deadline_exceeded) = vm.execute_block_transactions_with_deadline(
+        chain_state,
+        txns,
+        Some(block_gas_limit),
+        Some(deadline),
+    )?;
+    Ok((
+        outputs.into_iter().map(|(_, output)| output).collect(),
+        deadline_exceeded,
+    ))
+}
+
+/// Same as [`execute_block_transactions`], but also explain the status of every txn not executed
+/// successfully, see [`FailureExplanation`]. The explanation reads the failed module from
+/// `chain_state`, it is `None` if the failure can not be explained.
+/// The normal execution is untouched, only this debugging path pays for the explanation.
+pub fn execute_block_transactions_with_explanation(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<(TransactionOutput, Option<FailureExplanation>)>> {
+    let mut vm = StarcoinVM::new(metrics);
+    Ok(vm
+        .execute_block_transactions(chain_state, txns, Some(block_gas_limit))?
+        .into_iter()
+        .map(|(status, output)| (output, explain_failure(chain_state, status)))
+        .collect())
+}
+
+/// Explain a failed status, an explanation failure only loses the explanation.
+fn explain_failure(chain_state: &dyn StateView, status: VMStatus) -> Option<FailureExplanation> {
+    match status {
+        VMStatus::Executed => None,
+        status => explain_vm_status(chain_state, status)
+            .map_err(|e| debug!("Explain vm status failed: {:?}", e))
+            .ok(),
+    }
+}
+
+/// How the execution of a block txns stopped, see [`execute_block_transactions_with_stop`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExecutionStop {
+    /// All the txns are executed.
+    Completed,
+    /// The block gas is used up before the txn at `at_index`,
+    /// it and the txns after it are not executed.
+    GasLimitReached { at_index: usize },
+}
+
+/// Same as [`execute_block_transactions`], but also return whether all the txns are executed
+/// or the execution is truncated by the block gas limit, so the caller need not compare
+/// the count of outputs with the count of txns.
+pub fn execute_block_transactions_with_stop(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<(Vec<TransactionOutput>, ExecutionStop)> {
+    let txn_count = txns.len();
+    let outputs = execute_block_transactions(chain_state, txns, block_gas_limit, metrics)?;
+    let stop = if outputs.len() < txn_count {
+        ExecutionStop::GasLimitReached {
+            at_index: outputs.len(),
+        }
+    } else {
+        ExecutionStop::Completed
+    };
+    Ok((outputs, stop))
+}
+
+/// The result of a txn in [`execute_block_transactions_with_result`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TransactionExecutionResult {
+    /// The txn is executed, the output may be kept or discarded according to the status.
+    Executed(VMStatus, TransactionOutput),
+    /// The txn is skipped because the block gas is used up before it.
+    SkippedByGasLimit,
+    /// The user txn is skipped without execution because its max gas amount exceeds the
+    /// per txn gas limit, see [`execute_block_transactions_with_txn_gas_limit`].
+    SkippedByTxnGasLimit(VMStatus),
+}
+
+/// Same as [`execute_block_transactions`], but return a result for every txn in `txns`,
+/// the txns after the block gas is used up are returned as `SkippedByGasLimit`.
+pub fn execute_block_transactions_with_result(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<TransactionExecutionResult>> {
+    let txn_count = txns.len();
+    let mut vm = StarcoinVM::new(metrics);
+    let mut results: Vec<TransactionExecutionResult> = vm
+        .execute_block_transactions(chain_state, txns, Some(block_gas_limit))?
+        .into_iter()
+        .map(|(status, output)| TransactionExecutionResult::Executed(status, output))
+        .collect();
+    results.resize(txn_count, TransactionExecutionResult::SkippedByGasLimit);
+    Ok(results)
+}
+
+/// Same as [`execute_block_transactions_with_result`], but the user txns whose max gas amount
+/// exceeds `per_txn_gas_limit` are skipped without execution, the same rule as the txn pool,
+/// so they use no block gas. The skipped txns are returned as `SkippedByTxnGasLimit` at their
+/// position, and are not included in the block.
+pub fn execute_block_transactions_with_txn_gas_limit(
+    chain_state: &dyn StateView,
+    txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    per_txn_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<TransactionExecutionResult>> {
+    let mut skipped = Vec::with_capacity(txns.len());
+    let mut executed_txns = vec![];
+    for txn in txns {
+        let exceeded = matches!(
+            &txn,
+            Transaction::UserTransaction(user_txn) if user_txn.max_gas_amount() > per_txn_gas_limit
+        );
+        if exceeded {
+            skipped.push(Some(TransactionExecutionResult::SkippedByTxnGasLimit(
+                VMStatus::Error(StatusCode::MAX_GAS_UNITS_EXCEEDS_MAX_GAS_UNITS_BOUND),
+            )));
+        } else {
+            skipped.push(None);
+            executed_txns.push(txn);
+        }
+    }
+    let mut executed = execute_block_transactions_with_result(
+        chain_state,
+        executed_txns,
+        block_gas_limit,
+        metrics,
+    )?
+    .into_iter();
+    skipped
+        .into_iter()
+        .map(|result| {
+            result
+                .or_else(|| executed.next())
+                .ok_or_else(|| format_err!("Txn execution result must exist."))
+        })
+        .collect()
 }
 
 fn do_execute_block_transactions(
     chain_state: &dyn StateView,
     txns: Vec<Transaction>,
     block_gas_limit: Option<u64>,
+    gas_schedule: Option<CostTable>,
     metrics: Option<VMMetrics>,
-) -> Result<Vec<TransactionOutput>> {
+) -> Result<Vec<(u64, TransactionOutput)>> {
     let mut vm = StarcoinVM::new(metrics);
+    vm.set_gas_schedule_override(gas_schedule);
+    let state_view = CachingStateView::new(chain_state);
     let result = vm
-        .execute_block_transactions(chain_state, txns, block_gas_limit)?
+        .execute_block_transactions(&state_view, txns, block_gas_limit)?
         .into_iter()
         .map(|(_, output)| {
             debug! {"{:?}", output};
-            output
+            (output.gas_used(), output)
         })
         .collect();
     Ok(result)
 }
 
+/// Drop the modules cached by the shared `vm`, so the next execution loads the modules from the
+/// state it executes against, e.g. after a rollback or a reorg replaced the state underneath
+/// the vm. Otherwise the vm would keep executing the module bytecode loaded from the old state.
+/// The configs need no reset, they are reloaded from the state by every execution.
+pub fn reset_vm(vm: &Arc<Mutex<StarcoinVM>>) {
+    reset_locked_vm(&mut lock_vm(vm, "reset_vm"));
+}
+
+/// Same as [`reset_vm`], with a `vm` the caller has already locked.
+pub fn reset_locked_vm(vm: &mut StarcoinVM) {
+    vm.flush_module_cache();
+}
+
+/// Lock the shared `vm`, and record the wait time into `vm_lock_wait_time` of the vm metrics
+/// with the `method` label, to find whether the callers contend for the vm.
+/// The clock is only read when the vm is locked by others, so the uncontended lock costs
+/// the same as `lock()`, and nothing is recorded for a vm without metrics.
+/// The vm mutex does not poison, so a panic during one execution does not break the later ones.
+pub(crate) fn lock_vm<'a>(vm: &'a Mutex<StarcoinVM>, method: &str) -> MutexGuard<'a, StarcoinVM> {
+    if let Some(guard) = vm.try_lock() {
+        if let Some(metrics) = guard.metrics() {
+            metrics
+                .vm_lock_wait_time
+                .with_label_values(&[method])
+                .observe(0.0);
+        }
+        return guard;
+    }
+    let start = Instant::now();
+    let guard = vm.lock();
+    if let Some(metrics) = guard.metrics() {
+        metrics
+            .vm_lock_wait_time
+            .with_label_values(&[method])
+            .observe(start.elapsed().as_secs_f64());
+    }
+    guard
+}
+
+fn without_gas(outputs: Vec<(u64, TransactionOutput)>) -> Vec<TransactionOutput> {
+    outputs.into_iter().map(|(_, output)| output).collect()
+}
+
 pub fn validate_transaction(
     chain_state: &dyn StateView,
     txn: SignedUserTransaction,
     metrics: Option<VMMetrics>,
 ) -> Option<VMStatus> {
-    let mut vm = StarcoinVM::new(metrics);
+    validate_transaction_with_locked_vm(chain_state, txn, &mut StarcoinVM::new(metrics))
+}
+
+/// Validate the `txn` with a `vm` the caller has already locked, e.g. to validate in the middle
+/// of a routine holding the lock of the shared vm, where locking it again would deadlock.
+pub fn validate_transaction_with_locked_vm(
+    chain_state: &dyn StateView,
+    txn: SignedUserTransaction,
+    vm: &mut StarcoinVM,
+) -> Option<VMStatus> {
     vm.verify_transaction(chain_state, txn)
 }
 
+/// Validate a batch of txns with the shared `vm`, the vm is locked only once for the whole batch.
+/// The txns are validated independently, the results are returned in the order of `txns`.
+pub fn validate_transactions(
+    chain_state: &dyn StateView,
+    txns: Vec<SignedUserTransaction>,
+    vm: &Arc<Mutex<StarcoinVM>>,
+) -> Vec<Option<VMStatus>> {
+    validate_transactions_with_locked_vm(
+        chain_state,
+        txns,
+        &mut lock_vm(vm, "validate_transactions"),
+    )
+}
+
+/// Same as [`validate_transactions`], with a `vm` the caller has already locked.
+pub fn validate_transactions_with_locked_vm(
+    chain_state: &dyn StateView,
+    txns: Vec<SignedUserTransaction>,
+    vm: &mut StarcoinVM,
+) -> Vec<Option<VMStatus>> {
+    txns.into_iter()
+        .map(|txn| vm.verify_transaction(chain_state, txn))
+        .collect()
+}
+
+/// Validate the `txn` with a vm checked out from the `pool`.
+pub fn validate_transaction_with_pool(
+    chain_state: &dyn StateView,
+    txn: SignedUserTransaction,
+    pool: &VmPool,
+) -> Option<VMStatus> {
+    validate_transaction_with_locked_vm(chain_state, txn, &mut pool.get())
+}
+
+/// Validate a batch of txns with one vm checked out from the `pool`, see [`validate_transactions`].
+pub fn validate_transactions_with_pool(
+    chain_state: &dyn StateView,
+    txns: Vec<SignedUserTransaction>,
+    pool: &VmPool,
+) -> Vec<Option<VMStatus>> {
+    validate_transactions_with_locked_vm(chain_state, txns, &mut pool.get())
+}
+
 pub fn execute_readonly_function(
     chain_state: &dyn StateView,
     module: &ModuleId,
@@ -66,3 +507,319 @@ pub fn execute_readonly_function(
     let mut vm = StarcoinVM::new(metrics);
     vm.execute_readonly_function(chain_state, module, function_name, type_params, args)
 }
+
+/// Execute the readonly function `calls` against the same `chain_state` with one vm,
+/// so the modules loaded by the vm are reused by the later calls.
+/// The results are in the same order as `calls`, a failed call does not abort the others.
+pub fn execute_readonly_functions(
+    chain_state: &dyn StateView,
+    calls: Vec<(ModuleId, Identifier, Vec<TypeTag>, Vec<Vec<u8>>)>,
+    metrics: Option<VMMetrics>,
+) -> Vec<Result<Vec<Vec<u8>>, VMStatus>> {
+    let mut vm = StarcoinVM::new(metrics);
+    calls
+        .into_iter()
+        .map(|(module, function_name, type_params, args)| {
+            vm.execute_readonly_function(chain_state, &module, &function_name, type_params, args)
+        })
+        .collect()
+}
+
+/// Same as [`execute_readonly_function`], but also return the explained failure if the function fails,
+/// see [`FailureExplanation`]. Return `None` as the explanation if the failure can not be explained,
+/// e.g. the failed module can not be read from `chain_state`.
+pub fn execute_readonly_function_with_explanation(
+    chain_state: &dyn StateView,
+    module: &ModuleId,
+    function_name: &Identifier,
+    type_params: Vec<TypeTag>,
+    args: Vec<Vec<u8>>,
+    metrics: Option<VMMetrics>,
+) -> (Result<Vec<Vec<u8>>, VMStatus>, Option<FailureExplanation>) {
+    let result = execute_readonly_function(
+        chain_state,
+        module,
+        function_name,
+        type_params,
+        args,
+        metrics,
+    );
+    let explanation = match &result {
+        Ok(_) => None,
+        Err(status) => explain_failure(chain_state, status.clone()),
+    };
+    (result, explanation)
+}
+
+/// Same as [`execute_readonly_function`], but also return the events emitted by the function.
+pub fn execute_readonly_function_with_events(
+    chain_state: &dyn StateView,
+    module: &ModuleId,
+    function_name: &Identifier,
+    type_params: Vec<TypeTag>,
+    args: Vec<Vec<u8>>,
+    metrics: Option<VMMetrics>,
+) -> Result<(Vec<Vec<u8>>, Vec<ContractEvent>), VMStatus> {
+    let mut vm = StarcoinVM::new(metrics);
+    vm.execute_readonly_function_with_events(chain_state, module, function_name, type_params, args)
+}
+
+/// The failure of [`execute_readonly_function_with_deadline`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeadlineError {
+    /// The execution is aborted as it read the state after the deadline.
+    Exceeded,
+    /// The function failed by itself, the deadline did not stop it.
+    Vm(VMStatus),
+}
+
+impl Display for DeadlineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeadlineError::Exceeded => write!(f, "Execution deadline exceeded."),
+            DeadlineError::Vm(status) => write!(f, "{}", status),
+        }
+    }
+}
+
+impl std::error::Error for DeadlineError {}
+
+/// Execute a readonly function, abort the execution when it reads the state after the `deadline`.
+/// The vm can not be interrupted between instructions, so a function never reading the state
+/// runs to the end. Return [`DeadlineError::Exceeded`] only if the execution is stopped by the
+/// deadline, a vm error is returned as is even if the deadline passed meanwhile.
+pub fn execute_readonly_function_with_deadline(
+    chain_state: &dyn StateView,
+    module: &ModuleId,
+    function_name: &Identifier,
+    type_params: Vec<TypeTag>,
+    args: Vec<Vec<u8>>,
+    deadline: Instant,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<Vec<u8>>, DeadlineError> {
+    if Instant::now() >= deadline {
+        return Err(DeadlineError::Exceeded);
+    }
+    let state_view = DeadlineStateView {
+        state_view: chain_state,
+        deadline,
+        exceeded: Cell::new(false),
+    };
+    let mut vm = StarcoinVM::new(metrics);
+    vm.execute_readonly_function(&state_view, module, function_name, type_params, args)
+        .map_err(|status| {
+            if state_view.exceeded.get() {
+                DeadlineError::Exceeded
+            } else {
+                DeadlineError::Vm(status)
+            }
+        })
+}
+
+/// A state view memoizing the reads of the `state_view` for the execution of one block, so the
+/// txns of the block reading the same access path only read the underlying store once.
+/// The vm layers the write sets of the executed txns over this view, so the later txns see
+/// the writes of the earlier txns instead of the memoized reads, the same as reading the
+/// `state_view` directly. The failed reads are not memoized.
+pub struct CachingStateView<'a> {
+    state_view: &'a dyn StateView,
+    cache: RefCell<HashMap<AccessPath, Option<Vec<u8>>>>,
+}
+
+impl<'a> CachingStateView<'a> {
+    pub fn new(state_view: &'a dyn StateView) -> Self {
+        Self {
+            state_view,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<'a> StateView for CachingStateView<'a> {
+    fn get(&self, access_path: &AccessPath) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.cache.borrow().get(access_path) {
+            return Ok(value.clone());
+        }
+        let value = self.state_view.get(access_path)?;
+        self.cache
+            .borrow_mut()
+            .insert(access_path.clone(), value.clone());
+        Ok(value)
+    }
+
+    fn multi_get(&self, access_paths: &[AccessPath]) -> Result<Vec<Option<Vec<u8>>>> {
+        let missing: Vec<AccessPath> = {
+            let cache = self.cache.borrow();
+            access_paths
+                .iter()
+                .filter(|access_path| !cache.contains_key(access_path))
+                .cloned()
+                .collect()
+        };
+        if !missing.is_empty() {
+            let values = self.state_view.multi_get(&missing)?;
+            ensure!(
+                values.len() == missing.len(),
+                "Expect {} values, but got {}",
+                missing.len(),
+                values.len()
+            );
+            self.cache
+                .borrow_mut()
+                .extend(missing.into_iter().zip(values));
+        }
+        let cache = self.cache.borrow();
+        Ok(access_paths
+            .iter()
+            .map(|access_path| cache.get(access_path).cloned().flatten())
+            .collect())
+    }
+
+    fn is_genesis(&self) -> bool {
+        self.state_view.is_genesis()
+    }
+}
+
+/// A state view fail to read once the `deadline` passed, `exceeded` records a read refused.
+struct DeadlineStateView<'a> {
+    state_view: &'a dyn StateView,
+    deadline: Instant,
+    exceeded: Cell<bool>,
+}
+
+impl<'a> DeadlineStateView<'a> {
+    fn check_deadline(&self) -> Result<()> {
+        if Instant::now() >= self.deadline {
+            self.exceeded.set(true);
+            return Err(DeadlineError::Exceeded.into());
+        }
+        Ok(())
+    }
+}
+
+impl<'a> StateView for DeadlineStateView<'a> {
+    fn get(&self, access_path: &AccessPath) -> Result<Option<Vec<u8>>> {
+        self.check_deadline()?;
+        self.state_view.get(access_path)
+    }
+
+    fn multi_get(&self, access_paths: &[AccessPath]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.check_deadline()?;
+        self.state_view.multi_get(access_paths)
+    }
+
+    fn is_genesis(&self) -> bool {
+        self.state_view.is_genesis()
+    }
+}
+
+/// Execute a readonly function with the shared `vm`, avoid creating a new vm for every call.
+pub fn execute_readonly_function_with_vm(
+    chain_state: &dyn StateView,
+    module: &ModuleId,
+    function_name: &Identifier,
+    type_params: Vec<TypeTag>,
+    args: Vec<Vec<u8>>,
+    vm: &Arc<Mutex<StarcoinVM>>,
+) -> Result<Vec<Vec<u8>>, VMStatus> {
+    execute_readonly_function_with_locked_vm(
+        chain_state,
+        module,
+        function_name,
+        type_params,
+        args,
+        &mut lock_vm(vm, "execute_readonly_function"),
+    )
+}
+
+/// Same as [`execute_readonly_function_with_vm`], with a `vm` the caller has already locked.
+pub fn execute_readonly_function_with_locked_vm(
+    chain_state: &dyn StateView,
+    module: &ModuleId,
+    function_name: &Identifier,
+    type_params: Vec<TypeTag>,
+    args: Vec<Vec<u8>>,
+    vm: &mut StarcoinVM,
+) -> Result<Vec<Vec<u8>>, VMStatus> {
+    vm.execute_readonly_function(chain_state, module, function_name, type_params, args)
+}
+
+/// Execute a readonly function with a vm checked out from the `pool`.
+pub fn execute_readonly_function_with_pool(
+    chain_state: &dyn StateView,
+    module: &ModuleId,
+    function_name: &Identifier,
+    type_params: Vec<TypeTag>,
+    args: Vec<Vec<u8>>,
+    pool: &VmPool,
+) -> Result<Vec<Vec<u8>>, VMStatus> {
+    execute_readonly_function_with_locked_vm(
+        chain_state,
+        module,
+        function_name,
+        type_params,
+        args,
+        &mut pool.get(),
+    )
+}
+
+/// Simulate the `txn` with the shared `vm` and return its full output (status, gas, write set and events).
+/// Both user and block metadata txns are accepted, the txn is executed as a one txn block.
+/// The `chain_state` is only read and never changed,
+/// the caller is responsible for not applying the write set of the output.
+pub fn simulate_transaction(
+    chain_state: &dyn StateView,
+    txn: Transaction,
+    vm: &Arc<Mutex<StarcoinVM>>,
+) -> Result<TransactionOutput> {
+    simulate_transaction_with_locked_vm(chain_state, txn, &mut lock_vm(vm, "simulate_transaction"))
+}
+
+/// Same as [`simulate_transaction`], with a `vm` the caller has already locked.
+pub fn simulate_transaction_with_locked_vm(
+    chain_state: &dyn StateView,
+    txn: Transaction,
+    vm: &mut StarcoinVM,
+) -> Result<TransactionOutput> {
+    vm.execute_block_transactions(chain_state, vec![txn], None)?
+        .pop()
+        .map(|(_, output)| output)
+        .ok_or_else(|| format_err!("Txn output must exist."))
+}
+
+/// Estimate the gas used by the `txn` with the shared `vm`, the txn output is not applied to the `chain_state`.
+/// A txn kept on chain (including the aborted or out of gas txn) returns the gas it used,
+/// a discarded txn (e.g. failed in prologue) returns its VMStatus.
+/// Failing to load the vm configs returns `VM_STARTUP_FAILURE`, failing to execute the txn
+/// otherwise (e.g. reading the state) returns `STORAGE_ERROR`, the cause is logged.
+pub fn estimate_gas(
+    chain_state: &dyn StateView,
+    txn: SignedUserTransaction,
+    vm: &Arc<Mutex<StarcoinVM>>,
+) -> Result<u64, VMStatus> {
+    estimate_gas_with_locked_vm(chain_state, txn, &mut lock_vm(vm, "estimate_gas"))
+}
+
+/// Same as [`estimate_gas`], with a `vm` the caller has already locked.
+pub fn estimate_gas_with_locked_vm(
+    chain_state: &dyn StateView,
+    txn: SignedUserTransaction,
+    vm: &mut StarcoinVM,
+) -> Result<u64, VMStatus> {
+    if let Err(err) = vm.load_configs(chain_state) {
+        warn!("Load config error at estimate_gas: {}", err);
+        return Err(VMStatus::Error(StatusCode::VM_STARTUP_FAILURE));
+    }
+    let (status, output) = vm
+        .execute_block_transactions(chain_state, vec![Transaction::UserTransaction(txn)], None)
+        .map_err(|err| {
+            warn!("Execute txn error at estimate_gas: {}", err);
+            VMStatus::Error(StatusCode::STORAGE_ERROR)
+        })?
+        .pop()
+        .ok_or(VMStatus::Error(StatusCode::UNREACHABLE))?;
+    match output.status() {
+        TransactionStatus::Keep(_) => Ok(output.gas_used()),
+        TransactionStatus::Discard(_) => Err(status),
+    }
+}
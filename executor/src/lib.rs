@@ -4,8 +4,11 @@
 extern crate log;
 
 pub use account::Account;
-pub use block_executor::{block_execute, BlockExecutedData};
+pub use block_executor::{block_execute, execute_and_apply_block, BlockExecutedData};
 pub use executor::*;
+pub use parallel_executor::{
+    collect_access_set, collect_access_set_with_locked_vm, execute_block_transactions_parallel,
+};
 pub use starcoin_transaction_builder::{
     build_accept_token_txn, build_batch_transfer_txn, build_transfer_from_association,
     build_transfer_txn, build_transfer_txn_by_token_type,
@@ -14,6 +17,7 @@ pub use starcoin_transaction_builder::{
     encode_transfer_script_function, peer_to_peer_txn_sent_as_association, DEFAULT_EXPIRATION_TIME,
     DEFAULT_MAX_GAS_AMOUNT,
 };
+pub use vm_pool::{PooledVm, VmPool};
 pub use vm_runtime::metrics::VMMetrics;
 
 pub mod account;
@@ -24,7 +28,9 @@ pub mod error_code_test;
 mod executor;
 #[cfg(test)]
 pub mod module_compatibility_test;
+mod parallel_executor;
 #[cfg(test)]
 pub mod readonly_function_call_test;
 #[cfg(test)]
 pub mod script_function_test;
+mod vm_pool;
@@ -0,0 +1,317 @@
+// Copyright (c) The Starcoin Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::executor::{execute_block_transactions, lock_vm};
+use anyhow::{format_err, Result};
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use starcoin_types::transaction::{Transaction, TransactionOutput, TransactionStatus};
+use starcoin_vm_types::access_path::AccessPath;
+use starcoin_vm_types::state_view::StateView;
+use starcoin_vm_types::write_set::{WriteOp, WriteSet};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use vm_runtime::metrics::VMMetrics;
+use vm_runtime::starcoin_vm::StarcoinVM;
+
+/// Execute a block transactions with gas_limit, same as [`crate::execute_block_transactions`],
+/// but every run of consecutive user transactions is first executed speculatively in parallel.
+///
+/// The speculative outputs are then committed in block order: an output is only kept if none of
+/// the state it read has been written by an earlier transaction of the block, otherwise the
+/// transaction is re-executed on top of the committed state. The outputs and the gas limit
+/// early stop are identical to the sequential path. If a transaction writes the on-chain vm
+/// configs or publishes modules, the transactions from it onward are executed sequentially,
+/// because the speculative vms may run the modules cached before without recording them as read.
+/// Before the vm flushes the module cache on publishing, the outputs after an upgrade depend on the
+/// modules the sequential vm cached from the start of the block, so the whole block is executed
+/// sequentially instead.
+///
+/// Note: every user transaction updates the shared transaction fee resource in its epilogue,
+/// so transactions after the first of each run usually conflict and get re-executed.
+pub fn execute_block_transactions_parallel(
+    chain_state: &(dyn StateView + Sync),
+    mut txns: Vec<Transaction>,
+    block_gas_limit: u64,
+    metrics: Option<VMMetrics>,
+) -> Result<Vec<TransactionOutput>> {
+    let mut executor = ParallelExecutor::new(chain_state, block_gas_limit, metrics.clone())?;
+    if let Progress::ConfigChanged = executor.execute(&txns)? {
+        if !executor.flushes_module_cache_on_publish()? {
+            return execute_block_transactions(chain_state, txns, block_gas_limit, metrics);
+        }
+        // every txn before the one changing the configs is committed, so it is at outputs.len().
+        let rest = txns.split_off(executor.outputs.len());
+        executor.execute_sequential(rest)?;
+    }
+    Ok(executor.outputs)
+}
+
+/// Execute the `txn` with the shared `vm` against an instrumented view of the `chain_state`,
+/// return the access paths read and written by the txn, each sorted and deduplicated.
+/// The reads are every path the vm got from the state, including the vm configs,
+/// the writes are the paths in the write set of the output, the same write set as the full
+/// execution of the txn on the same state. A discarded txn writes nothing.
+/// The output is not applied, the `chain_state` is only read.
+pub fn collect_access_set(
+    chain_state: &dyn StateView,
+    txn: Transaction,
+    vm: &Arc<Mutex<StarcoinVM>>,
+) -> Result<(Vec<AccessPath>, Vec<AccessPath>)> {
+    collect_access_set_with_locked_vm(chain_state, txn, &mut lock_vm(vm, "collect_access_set"))
+}
+
+/// Same as [`collect_access_set`], with a `vm` the caller has already locked.
+pub fn collect_access_set_with_locked_vm(
+    chain_state: &dyn StateView,
+    txn: Transaction,
+    vm: &mut StarcoinVM,
+) -> Result<(Vec<AccessPath>, Vec<AccessPath>)> {
+    let recorder = ReadSetRecorder::new(chain_state);
+    let output = execute_single(vm, &recorder, txn)?;
+    let write_set: BTreeSet<AccessPath> = match output.status() {
+        TransactionStatus::Keep(_) => output
+            .write_set()
+            .iter()
+            .map(|(access_path, _)| access_path.clone())
+            .collect(),
+        TransactionStatus::Discard(_) => BTreeSet::new(),
+    };
+    Ok((
+        recorder.into_read_set().into_iter().collect(),
+        write_set.into_iter().collect(),
+    ))
+}
+
+enum Progress {
+    Continue,
+    GasExhausted,
+    ConfigChanged,
+}
+
+struct ParallelExecutor<'a> {
+    view: BlockStateView<'a>,
+    vm: StarcoinVM,
+    metrics: Option<VMMetrics>,
+    /// Access paths read when loading the vm configs.
+    config_paths: BTreeSet<AccessPath>,
+    gas_left: u64,
+    outputs: Vec<TransactionOutput>,
+}
+
+impl<'a> ParallelExecutor<'a> {
+    fn new(
+        chain_state: &'a (dyn StateView + Sync),
+        block_gas_limit: u64,
+        metrics: Option<VMMetrics>,
+    ) -> Result<Self> {
+        let mut vm = StarcoinVM::new(metrics.clone());
+        let recorder = ReadSetRecorder::new(chain_state);
+        vm.load_configs(&recorder)?;
+        Ok(Self {
+            view: BlockStateView::new(chain_state),
+            vm,
+            metrics,
+            config_paths: recorder.into_read_set(),
+            gas_left: block_gas_limit,
+            outputs: vec![],
+        })
+    }
+
+    fn execute(&mut self, txns: &[Transaction]) -> Result<Progress> {
+        let mut pos = 0;
+        while pos < txns.len() {
+            let progress = match &txns[pos] {
+                Transaction::BlockMetadata(_) => {
+                    let output = self.execute_one(txns[pos].clone())?;
+                    if self.changes_configs(&output) {
+                        return Ok(Progress::ConfigChanged);
+                    }
+                    pos += 1;
+                    self.commit(output, &mut BTreeSet::new());
+                    Progress::Continue
+                }
+                Transaction::UserTransaction(_) => {
+                    let end = txns[pos..]
+                        .iter()
+                        .position(|txn| !matches!(txn, Transaction::UserTransaction(_)))
+                        .map_or(txns.len(), |len| pos + len);
+                    let progress = self.execute_user_transactions(&txns[pos..end])?;
+                    pos = end;
+                    progress
+                }
+            };
+            if !matches!(progress, Progress::Continue) {
+                return Ok(progress);
+            }
+        }
+        Ok(Progress::Continue)
+    }
+
+    fn execute_user_transactions(&mut self, txns: &[Transaction]) -> Result<Progress> {
+        let view = &self.view;
+        let metrics = &self.metrics;
+        let speculations: Vec<Result<(BTreeSet<AccessPath>, TransactionOutput)>> = txns
+            .par_iter()
+            .map_init(
+                || StarcoinVM::new(metrics.clone()),
+                |vm, txn| {
+                    let recorder = ReadSetRecorder::new(view);
+                    let output = execute_single(vm, &recorder, txn.clone())?;
+                    Ok((recorder.into_read_set(), output))
+                },
+            )
+            .collect();
+
+        // paths written by the transactions committed since the speculation started.
+        let mut written = BTreeSet::new();
+        for (txn, speculation) in txns.iter().zip(speculations) {
+            let output = match speculation {
+                Ok((read_set, output)) if read_set.is_disjoint(&written) => output,
+                _ => self.execute_one(txn.clone())?,
+            };
+            if self.changes_configs(&output) {
+                return Ok(Progress::ConfigChanged);
+            }
+            match self.gas_left.checked_sub(output.gas_used()) {
+                Some(l) => self.gas_left = l,
+                None => return Ok(Progress::GasExhausted),
+            }
+            self.commit(output, &mut written);
+        }
+        Ok(Progress::Continue)
+    }
+
+    fn execute_one(&mut self, txn: Transaction) -> Result<TransactionOutput> {
+        execute_single(&mut self.vm, &self.view, txn)
+    }
+
+    /// Execute the rest `txns` of the block sequentially on top of the committed state,
+    /// with the block gas left.
+    fn execute_sequential(&mut self, txns: Vec<Transaction>) -> Result<()> {
+        let outputs = self
+            .vm
+            .execute_block_transactions(&self.view, txns, Some(self.gas_left))?;
+        self.outputs
+            .extend(outputs.into_iter().map(|(_, output)| output));
+        Ok(())
+    }
+
+    /// Whether the vm flushes the module cache on publishing with the configs of the committed
+    /// state, i.e. before the txn changing the configs.
+    fn flushes_module_cache_on_publish(&mut self) -> Result<bool> {
+        self.vm.load_configs(&self.view)?;
+        Ok(self.vm.flushes_module_cache_on_publish())
+    }
+
+    /// Whether the `output` writes the vm configs or publishes modules.
+    fn changes_configs(&self, output: &TransactionOutput) -> bool {
+        matches!(output.status(), TransactionStatus::Keep(_))
+            && output.write_set().iter().any(|(access_path, _)| {
+                access_path.path.is_code() || self.config_paths.contains(access_path)
+            })
+    }
+
+    fn commit(&mut self, output: TransactionOutput, written: &mut BTreeSet<AccessPath>) {
+        if let TransactionStatus::Keep(_) = output.status() {
+            let write_set = output.write_set();
+            written.extend(write_set.iter().map(|(access_path, _)| access_path.clone()));
+            self.view.push_write_set(write_set);
+        }
+        self.outputs.push(output);
+    }
+}
+
+fn execute_single(
+    vm: &mut StarcoinVM,
+    state_view: &dyn StateView,
+    txn: Transaction,
+) -> Result<TransactionOutput> {
+    vm.execute_block_transactions(state_view, vec![txn], None)?
+        .pop()
+        .map(|(_, output)| output)
+        .ok_or_else(|| format_err!("Execute transaction return empty output."))
+}
+
+/// State of the block being executed: the chain state plus the write sets committed so far.
+struct BlockStateView<'a> {
+    chain_state: &'a (dyn StateView + Sync),
+    changes: BTreeMap<AccessPath, Option<Vec<u8>>>,
+}
+
+impl<'a> BlockStateView<'a> {
+    fn new(chain_state: &'a (dyn StateView + Sync)) -> Self {
+        Self {
+            chain_state,
+            changes: BTreeMap::new(),
+        }
+    }
+
+    fn push_write_set(&mut self, write_set: &WriteSet) {
+        for (access_path, write_op) in write_set.iter() {
+            let value = match write_op {
+                WriteOp::Value(blob) => Some(blob.clone()),
+                WriteOp::Deletion => None,
+            };
+            self.changes.insert(access_path.clone(), value);
+        }
+    }
+}
+
+impl<'a> StateView for BlockStateView<'a> {
+    fn get(&self, access_path: &AccessPath) -> Result<Option<Vec<u8>>> {
+        match self.changes.get(access_path) {
+            Some(value) => Ok(value.clone()),
+            None => self.chain_state.get(access_path),
+        }
+    }
+
+    fn multi_get(&self, access_paths: &[AccessPath]) -> Result<Vec<Option<Vec<u8>>>> {
+        access_paths
+            .iter()
+            .map(|access_path| self.get(access_path))
+            .collect()
+    }
+
+    fn is_genesis(&self) -> bool {
+        self.chain_state.is_genesis()
+    }
+}
+
+/// Records every access path read through the wrapped state view.
+struct ReadSetRecorder<'a> {
+    state_view: &'a dyn StateView,
+    read_set: RefCell<BTreeSet<AccessPath>>,
+}
+
+impl<'a> ReadSetRecorder<'a> {
+    fn new(state_view: &'a dyn StateView) -> Self {
+        Self {
+            state_view,
+            read_set: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    fn into_read_set(self) -> BTreeSet<AccessPath> {
+        self.read_set.into_inner()
+    }
+}
+
+impl<'a> StateView for ReadSetRecorder<'a> {
+    fn get(&self, access_path: &AccessPath) -> Result<Option<Vec<u8>>> {
+        self.read_set.borrow_mut().insert(access_path.clone());
+        self.state_view.get(access_path)
+    }
+
+    fn multi_get(&self, access_paths: &[AccessPath]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.read_set
+            .borrow_mut()
+            .extend(access_paths.iter().cloned());
+        self.state_view.multi_get(access_paths)
+    }
+
+    fn is_genesis(&self) -> bool {
+        self.state_view.is_genesis()
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright (c) The Starcoin Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use parking_lot::{Condvar, Mutex};
+use std::ops::{Deref, DerefMut};
+use vm_runtime::metrics::VMMetrics;
+use vm_runtime::starcoin_vm::StarcoinVM;
+
+/// A pool of `StarcoinVM`, the vm is created lazily until `max_size` vms exist,
+/// so concurrent callers do not queue behind one shared vm.
+pub struct VmPool {
+    state: Mutex<PoolState>,
+    available: Condvar,
+    max_size: usize,
+    metrics: Option<VMMetrics>,
+}
+
+struct PoolState {
+    idle: Vec<StarcoinVM>,
+    created: usize,
+}
+
+impl VmPool {
+    /// Create a pool hold at most `max_size` vms, at least one vm is allowed.
+    pub fn new(max_size: usize, metrics: Option<VMMetrics>) -> Self {
+        Self {
+            state: Mutex::new(PoolState {
+                idle: vec![],
+                created: 0,
+            }),
+            available: Condvar::new(),
+            max_size: max_size.max(1),
+            metrics,
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Check out a vm, block until a vm is returned if all `max_size` vms are in use.
+    pub fn get(&self) -> PooledVm<'_> {
+        let mut state = self.state.lock();
+        loop {
+            if let Some(vm) = self.checkout(&mut state) {
+                return vm;
+            }
+            self.available.wait(&mut state);
+        }
+    }
+
+    /// Check out a vm, return None if all `max_size` vms are in use.
+    pub fn try_get(&self) -> Option<PooledVm<'_>> {
+        let mut state = self.state.lock();
+        self.checkout(&mut state)
+    }
+
+    fn checkout(&self, state: &mut PoolState) -> Option<PooledVm<'_>> {
+        let vm = match state.idle.pop() {
+            Some(vm) => vm,
+            None if state.created < self.max_size => {
+                state.created += 1;
+                StarcoinVM::new(self.metrics.clone())
+            }
+            None => return None,
+        };
+        Some(PooledVm {
+            pool: self,
+            vm: Some(vm),
+        })
+    }
+
+    fn release(&self, vm: StarcoinVM) {
+        self.state.lock().idle.push(vm);
+        self.available.notify_one();
+    }
+}
+
+/// A vm checked out from the [`VmPool`], it is returned to the pool when dropped.
+pub struct PooledVm<'a> {
+    pool: &'a VmPool,
+    vm: Option<StarcoinVM>,
+}
+
+impl<'a> Deref for PooledVm<'a> {
+    type Target = StarcoinVM;
+
+    fn deref(&self) -> &Self::Target {
+        self.vm.as_ref().expect("vm must exist before drop")
+    }
+}
+
+impl<'a> DerefMut for PooledVm<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.vm.as_mut().expect("vm must exist before drop")
+    }
+}
+
+impl<'a> Drop for PooledVm<'a> {
+    fn drop(&mut self) {
+        if let Some(mut vm) = self.vm.take() {
+            // a vm returned by a panicking thread may be left in the middle of an execution.
+            if std::thread::panicking() {
+                warn!("The pooled vm is returned during a panic, drop its module cache.");
+                vm.flush_module_cache();
+            }
+            self.pool.release(vm);
+        }
+    }
+}
@@ -5,7 +5,11 @@ use anyhow::anyhow;
 use anyhow::Result;
 use logger::prelude::*;
 use starcoin_executor::account::{create_account_txn_sent_as_association, peer_to_peer_txn};
-use starcoin_executor::{encode_create_account_script_function, validate_transaction, Account};
+use starcoin_executor::{
+    encode_create_account_script_function, encode_transfer_script_function, validate_transaction,
+    Account, ExecutionStop, FailureExplanation, TransactionExecutionResult, VMMetrics,
+};
+use starcoin_metrics::Registry;
 use starcoin_transaction_builder::{
     build_batch_script_function_same_amount, raw_peer_to_peer_txn, DEFAULT_EXPIRATION_TIME,
     DEFAULT_MAX_GAS_AMOUNT,
@@ -15,17 +19,22 @@ use starcoin_types::language_storage::ModuleId;
 use starcoin_types::transaction::{RawUserTransaction, ScriptFunction, TransactionArgument};
 use starcoin_types::{
     account_config, block_metadata::BlockMetadata, transaction::Transaction,
-    transaction::TransactionPayload, transaction::TransactionStatus,
+    transaction::TransactionOutput, transaction::TransactionPayload,
+    transaction::TransactionStatus,
 };
 use starcoin_vm_types::access_path::AccessPath;
 use starcoin_vm_types::account_config::genesis_address;
 use starcoin_vm_types::account_config::AccountResource;
-use starcoin_vm_types::genesis_config::ChainId;
+use starcoin_vm_types::gas_schedule::{GasAlgebra, GasCost};
+use starcoin_vm_types::genesis_config::{ChainId, StdlibVersion};
 use starcoin_vm_types::on_chain_config::{ConsensusConfig, OnChainConfig};
 use starcoin_vm_types::state_view::StateView;
 use starcoin_vm_types::token::stc::{stc_type_tag, STCUnit};
 use starcoin_vm_types::vm_status::KeptVMStatus;
-use starcoin_vm_types::{transaction::Package, vm_status::StatusCode};
+use starcoin_vm_types::{
+    transaction::Package,
+    vm_status::{StatusCode, VMStatus},
+};
 use test_helper::executor::{
     account_execute, account_execute_should_success, association_execute_should_success,
     blockmeta_execute, build_raw_txn, current_block_number, TEST_MODULE, TEST_MODULE_1,
@@ -34,12 +43,16 @@ use test_helper::executor::{
 
 use test_helper::executor::{
     compile_modules_with_address, execute_and_apply, get_balance, get_sequence_number,
-    prepare_genesis,
+    prepare_customized_genesis, prepare_genesis,
 };
 // use test_helper::Account;
+use parking_lot::Mutex;
+use starcoin_config::{BuiltinNetworkID, ChainNetwork};
 use starcoin_state_api::StateReaderExt;
 use starcoin_types::account_config::STC_TOKEN_CODE;
 use starcoin_vm_types::account_config::core_code_address;
+use statedb::ChainStateDB;
+use std::sync::Arc;
 use vm_runtime::starcoin_vm::StarcoinVM;
 
 #[derive(Default)]
@@ -59,6 +72,37 @@ impl StateView for NullStateView {
     }
 }
 
+/// Prepare the genesis state with an account funded by the association.
+fn prepare_funded_account() -> (ChainStateDB, ChainNetwork, Account) {
+    let (chain_state, net) = prepare_genesis();
+    let account = Account::new();
+    let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &account,
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    ));
+    execute_and_apply(&chain_state, txn);
+    (chain_state, net, account)
+}
+
+/// Build `count` transfers from the `sender` to new accounts, with the sequence numbers from 0.
+fn transfer_txns(sender: &Account, net: &ChainNetwork, count: u64) -> Vec<Transaction> {
+    (0..count)
+        .map(|seq_number| {
+            Transaction::UserTransaction(peer_to_peer_txn(
+                sender,
+                &Account::new(),
+                seq_number,
+                10_000,
+                net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+                net.chain_id(),
+            ))
+        })
+        .collect()
+}
+
 #[stest::test]
 fn test_vm_version() {
     let (chain_state, _net) = prepare_genesis();
@@ -726,6 +770,41 @@ fn test_execute_mint_txn_with_starcoin_vm() -> Result<()> {
     Ok(())
 }
 
+#[stest::test]
+fn test_execute_transaction() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+
+    let account = Account::new();
+    let txn =
+        starcoin_executor::build_transfer_from_association(*account.address(), 0, 1000, 1, &net);
+    let output = starcoin_executor::execute_transaction(&chain_state, txn.clone(), None)?;
+    assert_eq!(KeptVMStatus::Executed, output.status().status().unwrap());
+    let outputs = starcoin_executor::execute_transactions(&chain_state, vec![txn], None)?;
+    assert_eq!(outputs, vec![output]);
+
+    Ok(())
+}
+
+#[stest::test]
+fn test_execute_transaction_discarded() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+
+    // the sender account does not exist, the txn is discarded in prologue.
+    let txn = Transaction::UserTransaction(peer_to_peer_txn(
+        &Account::new(),
+        &Account::new(),
+        0,
+        10_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    ));
+    let output = starcoin_executor::execute_transaction(&chain_state, txn, None)?;
+    assert!(matches!(output.status(), TransactionStatus::Discard(_)));
+    assert_eq!(output.gas_used(), 0);
+
+    Ok(())
+}
+
 #[stest::test]
 fn test_execute_transfer_txn() -> Result<()> {
     let (chain_state, net) = prepare_genesis();
@@ -957,6 +1036,214 @@ fn test_publish_module_and_upgrade() -> Result<()> {
     Ok(())
 }
 
+#[stest::test]
+fn test_execute_with_explanation() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+
+    let account1 = Account::new();
+    let txn1 = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &account1, 0, 50_000_000, 1, &net,
+    ));
+    let output1 = execute_and_apply(&chain_state, txn1);
+    assert_eq!(KeptVMStatus::Executed, output1.status().status().unwrap());
+
+    let module_source = r#"
+        module {{sender}}::M {
+            public fun ok(): u64 {
+                1
+            }
+            public fun fail(): u64 {
+                abort 42
+            }
+            public fun overflow(): u64 {
+                let x = 18446744073709551615u64;
+                x + 1
+            }
+            public(script) fun overflow_script() {
+                Self::overflow();
+            }
+        }
+        "#;
+    let compiled_module = compile_modules_with_address(*account1.address(), module_source)
+        .pop()
+        .unwrap();
+    let txn = Transaction::UserTransaction(account1.create_signed_txn_impl(
+        *account1.address(),
+        TransactionPayload::Package(Package::new_with_module(compiled_module).unwrap()),
+        0,
+        100_000,
+        1,
+        1,
+        net.chain_id(),
+    ));
+    let output = execute_and_apply(&chain_state, txn);
+    assert_eq!(KeptVMStatus::Executed, output.status().status().unwrap());
+
+    let module_id = ModuleId::new(*account1.address(), Identifier::new("M").unwrap());
+    let call = |function: &str| {
+        starcoin_executor::execute_readonly_function_with_explanation(
+            &chain_state,
+            &module_id,
+            &Identifier::new(function).unwrap(),
+            vec![],
+            vec![],
+            None,
+        )
+    };
+    let (result, explanation) = call("ok");
+    assert!(result.is_ok());
+    assert!(explanation.is_none());
+
+    let (result, explanation) = call("fail");
+    assert!(matches!(result, Err(VMStatus::MoveAbort(_, 42))));
+    assert!(matches!(
+        explanation,
+        Some(FailureExplanation::MoveAbort { abort_code: 42, .. })
+    ));
+
+    let (result, explanation) = call("overflow");
+    assert!(matches!(result, Err(VMStatus::ExecutionFailure { .. })));
+    match explanation {
+        Some(FailureExplanation::ExecutionFailure { function_name, .. }) => {
+            assert_eq!(function_name.as_deref(), Some("overflow"))
+        }
+        explanation => panic!("unexpected explanation: {:?}", explanation),
+    }
+
+    let txn = Transaction::UserTransaction(account1.create_signed_txn_impl(
+        *account1.address(),
+        TransactionPayload::ScriptFunction(ScriptFunction::new(
+            module_id.clone(),
+            Identifier::new("overflow_script").unwrap(),
+            vec![],
+            vec![],
+        )),
+        1,
+        100_000,
+        1,
+        1,
+        net.chain_id(),
+    ));
+    let outputs = starcoin_executor::execute_block_transactions_with_explanation(
+        &chain_state,
+        vec![txn],
+        u64::MAX,
+        None,
+    )?;
+    assert_eq!(outputs.len(), 1);
+    match &outputs[0] {
+        (output, Some(FailureExplanation::ExecutionFailure { function_name, .. })) => {
+            assert!(matches!(
+                output.status(),
+                TransactionStatus::Keep(KeptVMStatus::ExecutionFailure { .. })
+            ));
+            assert_eq!(function_name.as_deref(), Some("overflow"));
+        }
+        (output, explanation) => panic!("unexpected output: {:?}, {:?}", output, explanation),
+    }
+    Ok(())
+}
+
+/// Publish a module M, call it, upgrade M and call the function added by the upgrade,
+/// all in one block, return the outputs of the four txns.
+fn upgrade_module_in_block(
+    chain_state: &ChainStateDB,
+    net: &ChainNetwork,
+) -> Result<Vec<TransactionOutput>> {
+    let account1 = Account::new();
+    let txn1 = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &account1, 0, 50_000_000, 1, net,
+    ));
+    let output1 = execute_and_apply(chain_state, txn1);
+    assert_eq!(KeptVMStatus::Executed, output1.status().status().unwrap());
+
+    let module_v1 = r#"
+        module {{sender}}::M {
+            public(script) fun hello(){
+            }
+        }
+        "#;
+    let module_v2 = r#"
+        module {{sender}}::M {
+            public(script) fun hello(){
+            }
+            public(script) fun hello2(){
+            }
+        }
+        "#;
+    let module_id = ModuleId::new(*account1.address(), Identifier::new("M").unwrap());
+    let publish = |source: &str| {
+        let compiled_module = compile_modules_with_address(*account1.address(), source)
+            .pop()
+            .unwrap();
+        TransactionPayload::Package(Package::new_with_module(compiled_module).unwrap())
+    };
+    let call = |function: &str| {
+        TransactionPayload::ScriptFunction(ScriptFunction::new(
+            module_id.clone(),
+            Identifier::new(function).unwrap(),
+            vec![],
+            vec![],
+        ))
+    };
+    // the vm caches M v1 when calling hello, then M is upgraded in the same block.
+    let txns = vec![
+        publish(module_v1),
+        call("hello"),
+        publish(module_v2),
+        call("hello2"),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(seq_number, payload)| {
+        Transaction::UserTransaction(account1.create_signed_txn_impl(
+            *account1.address(),
+            payload,
+            seq_number as u64,
+            100_000,
+            1,
+            1,
+            net.chain_id(),
+        ))
+    })
+    .collect::<Vec<_>>();
+    starcoin_executor::execute_transactions(chain_state, txns, None)
+}
+
+#[stest::test]
+fn test_upgrade_module_in_block() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let outputs = upgrade_module_in_block(&chain_state, &net)?;
+    assert_eq!(outputs.len(), 4);
+    for output in outputs {
+        assert_eq!(KeptVMStatus::Executed, output.status().status().unwrap());
+    }
+    Ok(())
+}
+
+#[stest::test]
+fn test_upgrade_module_in_block_before_flush_version() -> Result<()> {
+    let mut genesis_config = BuiltinNetworkID::Test.genesis_config().clone();
+    genesis_config.stdlib_version = StdlibVersion::Version(11);
+    let net = ChainNetwork::new_custom(
+        "upgrade_module_in_block_test".to_string(),
+        ChainId::new(100),
+        genesis_config,
+    )?;
+    let chain_state = prepare_customized_genesis(&net);
+    let outputs = upgrade_module_in_block(&chain_state, &net)?;
+    assert_eq!(outputs.len(), 4);
+    for output in &outputs[..3] {
+        assert_eq!(KeptVMStatus::Executed, output.status().status().unwrap());
+    }
+    // the call still runs the M cached before the upgrade, which has no hello2.
+    assert_ne!(
+        outputs[3].status(),
+        &TransactionStatus::Keep(KeptVMStatus::Executed)
+    );
+    Ok(())
+}
+
 #[stest::test]
 fn test_block_metadata() -> Result<()> {
     let (chain_state, net) = prepare_genesis();
@@ -987,3 +1274,1079 @@ fn test_block_metadata() -> Result<()> {
 
     Ok(())
 }
+
+#[stest::test]
+fn test_block_execute_parallel() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let account1 = Account::new();
+    let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &account1,
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    ));
+    execute_and_apply(&chain_state, txn);
+
+    let block_meta = BlockMetadata::new(
+        starcoin_crypto::HashValue::random(),
+        net.time_service().now_millis(),
+        *account1.address(),
+        Some(account1.auth_key()),
+        0,
+        current_block_number(&chain_state) + 1,
+        net.chain_id(),
+        0,
+    );
+    let mut txns = (0u64..10)
+        .map(|seq_number| {
+            Transaction::UserTransaction(peer_to_peer_txn(
+                &account1,
+                &Account::new(),
+                seq_number,
+                10_000,
+                net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+                net.chain_id(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    txns.insert(0, Transaction::BlockMetadata(block_meta));
+
+    for block_gas_limit in [u64::MAX, 3_000] {
+        let sequential = starcoin_executor::execute_block_transactions(
+            &chain_state,
+            txns.clone(),
+            block_gas_limit,
+            None,
+        )?;
+        let parallel = starcoin_executor::execute_block_transactions_parallel(
+            &chain_state,
+            txns.clone(),
+            block_gas_limit,
+            None,
+        )?;
+        assert_eq!(sequential, parallel);
+    }
+    Ok(())
+}
+
+#[stest::test]
+fn test_block_execute_parallel_with_conflicts() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let accounts = (0..3).map(|_| Account::new()).collect::<Vec<_>>();
+    for account in accounts.iter() {
+        let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+            account,
+            get_sequence_number(account_config::association_address(), &chain_state),
+            50_000_000,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            &net,
+        ));
+        execute_and_apply(&chain_state, txn);
+    }
+
+    let transfer = |sender: &Account, receiver: &Account, seq_number: u64| {
+        Transaction::UserTransaction(peer_to_peer_txn(
+            sender,
+            receiver,
+            seq_number,
+            10_000,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            net.chain_id(),
+        ))
+    };
+    let txns = vec![
+        // independent transfers to new accounts.
+        transfer(&accounts[0], &Account::new(), 0),
+        transfer(&accounts[1], &Account::new(), 0),
+        // same sender as the first txn.
+        transfer(&accounts[0], &Account::new(), 1),
+        // receiver is the sender of the second txn.
+        transfer(&accounts[2], &accounts[1], 0),
+        // depends on the balance received in the previous txn.
+        transfer(&accounts[1], &accounts[0], 1),
+    ];
+
+    let sequential =
+        starcoin_executor::execute_block_transactions(&chain_state, txns.clone(), u64::MAX, None)?;
+    let parallel =
+        starcoin_executor::execute_block_transactions_parallel(&chain_state, txns, u64::MAX, None)?;
+    assert_eq!(sequential.len(), 5);
+    assert_eq!(sequential, parallel);
+    Ok(())
+}
+
+#[stest::test]
+fn test_block_execute_parallel_with_gas_token_and_block_metadata() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let accounts = (0..2).map(|_| Account::new()).collect::<Vec<_>>();
+    for account in accounts.iter() {
+        let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+            account,
+            get_sequence_number(account_config::association_address(), &chain_state),
+            50_000_000,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            &net,
+        ));
+        execute_and_apply(&chain_state, txn);
+    }
+
+    let transfer = |sender: &Account, seq_number: u64| {
+        Transaction::UserTransaction(peer_to_peer_txn(
+            sender,
+            &Account::new(),
+            seq_number,
+            10_000,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            net.chain_id(),
+        ))
+    };
+    // pays the gas with a token other than STC.
+    let dummy_gas_txn =
+        Transaction::UserTransaction(accounts[1].sign_txn(RawUserTransaction::new(
+            *accounts[1].address(),
+            0,
+            TransactionPayload::ScriptFunction(encode_transfer_script_function(
+                *Account::new().address(),
+                10_000,
+            )),
+            DEFAULT_MAX_GAS_AMOUNT,
+            1,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            net.chain_id(),
+            "0x1::DummyToken::DummyToken".to_string(),
+        )));
+    let block_meta = Transaction::BlockMetadata(BlockMetadata::new(
+        starcoin_crypto::HashValue::random(),
+        net.time_service().now_millis(),
+        *accounts[0].address(),
+        Some(accounts[0].auth_key()),
+        0,
+        current_block_number(&chain_state) + 1,
+        net.chain_id(),
+        0,
+    ));
+    let txns = vec![
+        block_meta,
+        transfer(&accounts[0], 0),
+        dummy_gas_txn,
+        transfer(&accounts[1], 0),
+        transfer(&accounts[0], 1),
+    ];
+
+    let sequential =
+        starcoin_executor::execute_block_transactions(&chain_state, txns.clone(), u64::MAX, None)?;
+    let parallel =
+        starcoin_executor::execute_block_transactions_parallel(&chain_state, txns, u64::MAX, None)?;
+    assert_eq!(sequential.len(), 5);
+    assert!(matches!(
+        sequential[2].status(),
+        TransactionStatus::Discard(_)
+    ));
+    for (sequential, parallel) in sequential.iter().zip(parallel.iter()) {
+        assert_eq!(sequential.write_set(), parallel.write_set());
+    }
+    assert_eq!(sequential, parallel);
+    Ok(())
+}
+
+#[stest::test]
+fn test_collect_access_set() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let account1 = Account::new();
+    let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &account1,
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    ));
+    execute_and_apply(&chain_state, txn);
+
+    let txn = Transaction::UserTransaction(peer_to_peer_txn(
+        &account1,
+        &Account::new(),
+        0,
+        10_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    ));
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    let (reads, writes) = starcoin_executor::collect_access_set(&chain_state, txn.clone(), &vm)?;
+    let output = starcoin_executor::execute_transaction(&chain_state, txn, None)?;
+    let mut expect_writes = output
+        .write_set()
+        .iter()
+        .map(|(access_path, _)| access_path.clone())
+        .collect::<Vec<_>>();
+    expect_writes.sort();
+    assert_eq!(writes, expect_writes);
+    // the sender's account and balance are read before they are updated.
+    let sender_writes = writes
+        .iter()
+        .filter(|access_path| access_path.address == *account1.address())
+        .collect::<Vec<_>>();
+    assert!(!sender_writes.is_empty());
+    assert!(sender_writes
+        .into_iter()
+        .all(|access_path| reads.contains(access_path)));
+
+    // a discarded txn writes nothing.
+    let txn = Transaction::UserTransaction(peer_to_peer_txn(
+        &Account::new(),
+        &Account::new(),
+        0,
+        10_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    ));
+    let (reads, writes) = starcoin_executor::collect_access_set(&chain_state, txn, &vm)?;
+    assert!(!reads.is_empty());
+    assert!(writes.is_empty());
+    Ok(())
+}
+
+#[stest::test]
+fn test_block_execute_with_txn() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let account1 = Account::new();
+    let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &account1,
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    ));
+    execute_and_apply(&chain_state, txn);
+
+    let txns = (0u64..10)
+        .map(|seq_number| {
+            Transaction::UserTransaction(peer_to_peer_txn(
+                &account1,
+                &Account::new(),
+                seq_number,
+                10_000,
+                net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+                net.chain_id(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    let block_gas_limit = 3_000;
+    let executed = starcoin_executor::execute_block_transactions_with_txn(
+        &chain_state,
+        txns.clone(),
+        block_gas_limit,
+        None,
+    )?;
+    assert!(!executed.is_empty() && executed.len() < txns.len());
+    let block_gas_used = executed.iter().fold(0u64, |acc, (_, gas_used, output)| {
+        assert_eq!(*gas_used, output.gas_used());
+        acc + gas_used
+    });
+    assert!(block_gas_used <= block_gas_limit);
+    for (executed_txn, txn) in executed.iter().zip(txns.iter()) {
+        assert_eq!(&executed_txn.0, txn);
+    }
+    Ok(())
+}
+
+#[stest::test]
+fn test_estimate_gas() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let account1 = Account::new();
+    let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &account1,
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    ));
+    execute_and_apply(&chain_state, txn);
+
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    let txn = peer_to_peer_txn(
+        &account1,
+        &Account::new(),
+        0,
+        10_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    );
+    let estimated_gas = starcoin_executor::estimate_gas(&chain_state, txn.clone(), &vm)
+        .map_err(|status| anyhow!("estimate gas failed: {:?}", status))?;
+    let output = execute_and_apply(&chain_state, Transaction::UserTransaction(txn));
+    assert_eq!(estimated_gas, output.gas_used());
+
+    // sequence number 0 has been used.
+    let txn = peer_to_peer_txn(
+        &account1,
+        &Account::new(),
+        0,
+        10_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    );
+    let status = starcoin_executor::estimate_gas(&chain_state, txn, &vm).unwrap_err();
+    assert_eq!(status.status_code(), StatusCode::SEQUENCE_NUMBER_TOO_OLD);
+    Ok(())
+}
+
+#[stest::test]
+fn test_estimate_gas_of_aborted_txn() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let account1 = Account::new();
+    let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &account1,
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    ));
+    execute_and_apply(&chain_state, txn);
+
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    // transfer more than the balance, the txn aborts but still consumes gas.
+    let txn = peer_to_peer_txn(
+        &account1,
+        &Account::new(),
+        0,
+        100_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    );
+    let estimated_gas = starcoin_executor::estimate_gas(&chain_state, txn.clone(), &vm)
+        .map_err(|status| anyhow!("estimate gas failed: {:?}", status))?;
+    assert!(estimated_gas > 0);
+    // estimate gas does not change the chain state.
+    assert_eq!(get_sequence_number(*account1.address(), &chain_state), 0);
+
+    let output = execute_and_apply(&chain_state, Transaction::UserTransaction(txn));
+    assert!(matches!(
+        output.status(),
+        TransactionStatus::Keep(KeptVMStatus::MoveAbort(_, _))
+    ));
+    assert_eq!(estimated_gas, output.gas_used());
+    Ok(())
+}
+
+#[stest::test]
+fn test_vm_pool() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let pool = starcoin_executor::VmPool::new(2, None);
+    {
+        let _vm1 = pool.get();
+        let _vm2 = pool.try_get().expect("pool is not exhausted");
+        assert!(pool.try_get().is_none());
+    }
+    assert!(pool.try_get().is_some());
+
+    let txn = create_account_txn_sent_as_association(
+        &Account::new(),
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    );
+    assert!(starcoin_executor::validate_transaction_with_pool(&chain_state, txn, &pool).is_none());
+    Ok(())
+}
+
+#[stest::test]
+fn test_validate_transactions() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let sequence_number = get_sequence_number(account_config::association_address(), &chain_state);
+    let create_txn = |seq_number: u64| {
+        create_account_txn_sent_as_association(
+            &Account::new(),
+            seq_number,
+            50_000_000,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            &net,
+        )
+    };
+    let valid_txn = create_txn(sequence_number);
+    // the txn of association account signed by another account.
+    let invalid_txn = Account::new().sign_txn(create_txn(sequence_number).into_raw_transaction());
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    let txns = vec![invalid_txn, valid_txn];
+    let results = starcoin_executor::validate_transactions(&chain_state, txns.clone(), &vm);
+    assert_eq!(results.len(), 2);
+    assert_eq!(
+        results[0].as_ref().map(|status| status.status_code()),
+        Some(StatusCode::INVALID_AUTH_KEY)
+    );
+    assert!(results[1].is_none());
+
+    let pool = starcoin_executor::VmPool::new(1, None);
+    assert_eq!(
+        starcoin_executor::validate_transactions_with_pool(&chain_state, txns, &pool),
+        results
+    );
+    Ok(())
+}
+
+#[stest::test]
+fn test_validate_transaction_with_locked_vm() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let txn = create_account_txn_sent_as_association(
+        &Account::new(),
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    );
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    // the caller holds the lock of the shared vm, and reuses it instead of locking again.
+    let mut locked_vm = vm.lock();
+    assert!(starcoin_executor::validate_transaction_with_locked_vm(
+        &chain_state,
+        txn.clone(),
+        &mut locked_vm
+    )
+    .is_none());
+    let gas_used =
+        starcoin_executor::estimate_gas_with_locked_vm(&chain_state, txn.clone(), &mut locked_vm)
+            .map_err(|status| anyhow!("estimate gas failed: {:?}", status))?;
+    drop(locked_vm);
+    assert_eq!(
+        starcoin_executor::estimate_gas(&chain_state, txn, &vm)
+            .map_err(|status| anyhow!("estimate gas failed: {:?}", status))?,
+        gas_used
+    );
+    Ok(())
+}
+
+#[stest::test]
+fn test_execute_with_deadline() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let deadline = std::time::Instant::now();
+
+    let account_module = ModuleId::new(core_code_address(), Identifier::new("Account").unwrap());
+    let err = starcoin_executor::execute_readonly_function_with_deadline(
+        &chain_state,
+        &account_module,
+        &Identifier::new("sequence_number").unwrap(),
+        vec![],
+        vec![bcs_ext::to_bytes(&account_config::association_address())?],
+        deadline,
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(err, starcoin_executor::DeadlineError::Exceeded);
+
+    // a failure before the deadline is the vm status, not a deadline error.
+    let err = starcoin_executor::execute_readonly_function_with_deadline(
+        &chain_state,
+        &account_module,
+        &Identifier::new("no_such_function").unwrap(),
+        vec![],
+        vec![],
+        deadline + std::time::Duration::from_secs(60),
+        None,
+    )
+    .unwrap_err();
+    assert!(matches!(err, starcoin_executor::DeadlineError::Vm(_)));
+
+    let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &Account::new(),
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    ));
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    let (outputs, deadline_exceeded) = starcoin_executor::execute_block_transactions_with_deadline(
+        &chain_state,
+        vec![txn.clone()],
+        u64::MAX,
+        deadline,
+        &vm,
+    )?;
+    assert!(outputs.is_empty());
+    assert!(deadline_exceeded);
+
+    let (outputs, deadline_exceeded) = starcoin_executor::execute_block_transactions_with_deadline(
+        &chain_state,
+        vec![txn],
+        u64::MAX,
+        deadline + std::time::Duration::from_secs(60),
+        &vm,
+    )?;
+    assert_eq!(outputs.len(), 1);
+    assert!(!deadline_exceeded);
+    Ok(())
+}
+
+#[stest::test]
+fn test_block_execute_with_result() -> Result<()> {
+    let (chain_state, net, account1) = prepare_funded_account();
+    let txns = transfer_txns(&account1, &net, 10);
+    let results =
+        starcoin_executor::execute_block_transactions_with_result(&chain_state, txns, 3_000, None)?;
+    assert_eq!(results.len(), 10);
+    let executed = results
+        .iter()
+        .take_while(|result| matches!(result, TransactionExecutionResult::Executed(_, _)))
+        .count();
+    assert!(executed > 0 && executed < results.len());
+    assert!(results[executed..]
+        .iter()
+        .all(|result| *result == TransactionExecutionResult::SkippedByGasLimit));
+    Ok(())
+}
+
+#[stest::test]
+fn test_block_execute_with_stop() -> Result<()> {
+    let (chain_state, net, account1) = prepare_funded_account();
+    let txns = transfer_txns(&account1, &net, 10);
+    let (outputs, stop) = starcoin_executor::execute_block_transactions_with_stop(
+        &chain_state,
+        txns.clone(),
+        3_000,
+        None,
+    )?;
+    assert!(!outputs.is_empty() && outputs.len() < txns.len());
+    assert_eq!(
+        stop,
+        ExecutionStop::GasLimitReached {
+            at_index: outputs.len()
+        }
+    );
+
+    let (outputs, stop) = starcoin_executor::execute_block_transactions_with_stop(
+        &chain_state,
+        txns,
+        u64::MAX,
+        None,
+    )?;
+    assert_eq!(outputs.len(), 10);
+    assert_eq!(stop, ExecutionStop::Completed);
+    Ok(())
+}
+
+#[stest::test]
+fn test_execute_transactions_with_metadata() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let miner = Account::new();
+    let block_number = current_block_number(&chain_state);
+    let block_meta = BlockMetadata::new(
+        starcoin_crypto::HashValue::random(),
+        net.time_service().now_millis(),
+        *miner.address(),
+        Some(miner.auth_key()),
+        0,
+        block_number + 1,
+        net.chain_id(),
+        0,
+    );
+    let txn = create_account_txn_sent_as_association(
+        &Account::new(),
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    );
+    // the sender does not exist, so the txn is discarded, but its output is still returned.
+    let discarded_txn = peer_to_peer_txn(
+        &Account::new(),
+        &Account::new(),
+        0,
+        10_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    );
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    let outputs = starcoin_executor::execute_transactions_with_metadata(
+        &chain_state,
+        block_meta,
+        vec![txn, discarded_txn],
+        &vm,
+    )?;
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(
+        outputs[0].status(),
+        &TransactionStatus::Keep(KeptVMStatus::Executed)
+    );
+    assert!(matches!(outputs[1].status(), TransactionStatus::Discard(_)));
+    // the chain state is not changed.
+    assert_eq!(current_block_number(&chain_state), block_number);
+    Ok(())
+}
+
+#[stest::test]
+fn test_simulate_transaction() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let sequence_number = get_sequence_number(account_config::association_address(), &chain_state);
+    let txn = create_account_txn_sent_as_association(
+        &Account::new(),
+        sequence_number,
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    );
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    let output = starcoin_executor::simulate_transaction(
+        &chain_state,
+        Transaction::UserTransaction(txn),
+        &vm,
+    )?;
+    assert_eq!(
+        output.status(),
+        &TransactionStatus::Keep(KeptVMStatus::Executed)
+    );
+    assert!(output.gas_used() > 0);
+    assert!(!output.write_set().is_empty());
+    assert!(!output.events().is_empty());
+    assert_eq!(
+        get_sequence_number(account_config::association_address(), &chain_state),
+        sequence_number
+    );
+    Ok(())
+}
+
+#[stest::test]
+fn test_execute_readonly_function_with_events() -> Result<()> {
+    let (chain_state, _net) = prepare_genesis();
+    let (mut result, events) = starcoin_executor::execute_readonly_function_with_events(
+        &chain_state,
+        &ModuleId::new(genesis_address(), Identifier::new("Block").unwrap()),
+        &Identifier::new("get_current_block_number").unwrap(),
+        vec![],
+        vec![],
+        None,
+    )
+    .map_err(|status| anyhow!("execute readonly function failed: {:?}", status))?;
+    assert!(events.is_empty());
+    assert_eq!(result.len(), 1);
+    let block_number: u64 = bcs_ext::from_bytes(result.pop().unwrap().as_slice())?;
+    assert_eq!(block_number, current_block_number(&chain_state));
+    Ok(())
+}
+
+#[stest::test]
+fn test_execute_readonly_functions() -> Result<()> {
+    let (chain_state, _net) = prepare_genesis();
+    let block_module = ModuleId::new(genesis_address(), Identifier::new("Block").unwrap());
+    let mut results = starcoin_executor::execute_readonly_functions(
+        &chain_state,
+        vec![
+            (
+                block_module.clone(),
+                Identifier::new("get_current_block_number").unwrap(),
+                vec![],
+                vec![],
+            ),
+            (
+                block_module.clone(),
+                Identifier::new("not_exist_function").unwrap(),
+                vec![],
+                vec![],
+            ),
+            (
+                block_module,
+                Identifier::new("get_current_block_number").unwrap(),
+                vec![],
+                vec![],
+            ),
+        ],
+        None,
+    );
+    assert_eq!(results.len(), 3);
+    // the failed call does not abort the later calls.
+    assert!(results[1].is_err());
+    for result in [results.remove(2), results.remove(0)] {
+        let mut result =
+            result.map_err(|status| anyhow!("execute readonly function failed: {:?}", status))?;
+        let block_number: u64 = bcs_ext::from_bytes(result.pop().unwrap().as_slice())?;
+        assert_eq!(block_number, current_block_number(&chain_state));
+    }
+    Ok(())
+}
+
+#[stest::test]
+fn test_block_execute_with_gas_schedule() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+        &Account::new(),
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    ));
+    let mut gas_schedule = starcoin_config::LATEST_GAS_SCHEDULE.clone();
+    for cost in gas_schedule.instruction_table.iter_mut() {
+        *cost = GasCost::new(cost.instruction_gas.get() * 10, cost.memory_gas.get());
+    }
+
+    let gas_used = |gas_schedule| -> Result<u64> {
+        let output = starcoin_executor::execute_block_transactions_with_gas_schedule(
+            &chain_state,
+            vec![txn.clone()],
+            u64::MAX,
+            gas_schedule,
+            None,
+        )?
+        .pop()
+        .expect("Output must exist.");
+        assert_eq!(
+            output.status(),
+            &TransactionStatus::Keep(KeptVMStatus::Executed)
+        );
+        Ok(output.gas_used())
+    };
+    let default_gas_used = gas_used(None)?;
+    assert_eq!(
+        default_gas_used,
+        starcoin_executor::execute_transactions(&chain_state, vec![txn.clone()], None)?
+            .pop()
+            .expect("Output must exist.")
+            .gas_used()
+    );
+    assert!(gas_used(Some(gas_schedule))? > default_gas_used);
+    Ok(())
+}
+
+/// Count the reads of every access path.
+struct CountingStateView<'a> {
+    state_view: &'a dyn StateView,
+    reads: Mutex<std::collections::HashMap<AccessPath, usize>>,
+}
+
+impl<'a> StateView for CountingStateView<'a> {
+    fn get(&self, access_path: &AccessPath) -> Result<Option<Vec<u8>>> {
+        *self.reads.lock().entry(access_path.clone()).or_default() += 1;
+        self.state_view.get(access_path)
+    }
+
+    fn multi_get(&self, access_paths: &[AccessPath]) -> Result<Vec<Option<Vec<u8>>>> {
+        access_paths
+            .iter()
+            .map(|access_path| self.get(access_path))
+            .collect()
+    }
+
+    fn is_genesis(&self) -> bool {
+        self.state_view.is_genesis()
+    }
+}
+
+#[stest::test]
+fn test_block_execute_reads_once() -> Result<()> {
+    let (chain_state, net, account1) = prepare_funded_account();
+    let txns = transfer_txns(&account1, &net, 5);
+    let state_view = CountingStateView {
+        state_view: &chain_state,
+        reads: Mutex::new(std::collections::HashMap::new()),
+    };
+    let outputs =
+        starcoin_executor::execute_block_transactions(&state_view, txns.clone(), u64::MAX, None)?;
+    let reads = state_view.reads.into_inner();
+    assert!(!reads.is_empty());
+    assert!(reads.values().all(|count| *count == 1));
+
+    // the later txns still see the writes of the earlier txns.
+    let expect_outputs =
+        StarcoinVM::new(None).execute_block_transactions(&chain_state, txns, None)?;
+    assert_eq!(outputs.len(), expect_outputs.len());
+    for (output, (_, expect_output)) in outputs.iter().zip(expect_outputs.iter()) {
+        assert_eq!(output.status(), expect_output.status());
+        assert_eq!(output.gas_used(), expect_output.gas_used());
+        assert_eq!(output.write_set(), expect_output.write_set());
+    }
+    Ok(())
+}
+
+#[stest::test]
+fn test_block_execute_with_txn_gas_limit() -> Result<()> {
+    let (chain_state, net, account1) = prepare_funded_account();
+
+    let transfer = |seq_number: u64, max_gas: u64| {
+        Transaction::UserTransaction(account1.sign_txn(raw_peer_to_peer_txn(
+            *account1.address(),
+            *Account::new().address(),
+            10_000,
+            seq_number,
+            1,
+            max_gas,
+            STC_TOKEN_CODE.clone(),
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            net.chain_id(),
+        )))
+    };
+    let per_txn_gas_limit = DEFAULT_MAX_GAS_AMOUNT / 2;
+    let txns = vec![
+        transfer(0, per_txn_gas_limit),
+        transfer(1, per_txn_gas_limit + 1),
+        // the skipped txn does not take the sequence number.
+        transfer(1, per_txn_gas_limit),
+    ];
+    let results = starcoin_executor::execute_block_transactions_with_txn_gas_limit(
+        &chain_state,
+        txns,
+        u64::MAX,
+        per_txn_gas_limit,
+        None,
+    )?;
+    assert_eq!(results.len(), 3);
+    assert_eq!(
+        results[1],
+        TransactionExecutionResult::SkippedByTxnGasLimit(VMStatus::Error(
+            StatusCode::MAX_GAS_UNITS_EXCEEDS_MAX_GAS_UNITS_BOUND
+        ))
+    );
+    for result in [&results[0], &results[2]] {
+        match result {
+            TransactionExecutionResult::Executed(_, output) => {
+                assert_eq!(KeptVMStatus::Executed, output.status().status().unwrap())
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+    Ok(())
+}
+
+#[stest::test]
+fn test_vm_lock_wait_time() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let metrics = VMMetrics::register(&Registry::new())?;
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(Some(metrics.clone()))));
+    let txn = peer_to_peer_txn(
+        &Account::new(),
+        &Account::new(),
+        0,
+        10_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    );
+    let lock_wait_time = metrics
+        .vm_lock_wait_time
+        .with_label_values(&["validate_transactions"]);
+
+    starcoin_executor::validate_transactions(&chain_state, vec![txn.clone()], &vm);
+    assert_eq!(lock_wait_time.get_sample_count(), 1);
+
+    // the contended lock records the wait for the holder to release the vm.
+    let guard = vm.lock();
+    let validation = {
+        let vm = vm.clone();
+        std::thread::spawn(move || {
+            starcoin_executor::validate_transactions(&chain_state, vec![txn], &vm);
+        })
+    };
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    drop(guard);
+    validation.join().unwrap();
+    assert_eq!(lock_wait_time.get_sample_count(), 2);
+    assert!(lock_wait_time.get_sample_sum() >= 0.05);
+    Ok(())
+}
+
+/// A state view panics on every read.
+struct PanicStateView;
+
+impl StateView for PanicStateView {
+    fn get(&self, _access_path: &AccessPath) -> Result<Option<Vec<u8>>> {
+        panic!("state view panics")
+    }
+
+    fn multi_get(&self, _access_paths: &[AccessPath]) -> Result<Vec<Option<Vec<u8>>>> {
+        panic!("state view panics")
+    }
+
+    fn is_genesis(&self) -> bool {
+        false
+    }
+}
+
+#[stest::test]
+fn test_shared_vm_survives_panic() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    let txn = Transaction::UserTransaction(peer_to_peer_txn(
+        &Account::new(),
+        &Account::new(),
+        0,
+        10_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        net.chain_id(),
+    ));
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        starcoin_executor::simulate_transaction(&PanicStateView, txn.clone(), &vm)
+    }));
+    assert!(panicked.is_err());
+
+    // the later executions with the same vm still work.
+    let output = starcoin_executor::simulate_transaction(&chain_state, txn, &vm)?;
+    assert!(matches!(output.status(), TransactionStatus::Discard(_)));
+    Ok(())
+}
+
+#[stest::test]
+fn test_vm_pool_survives_panic() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let txn = create_account_txn_sent_as_association(
+        &Account::new(),
+        get_sequence_number(account_config::association_address(), &chain_state),
+        50_000_000,
+        net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+        &net,
+    );
+    let pool = starcoin_executor::VmPool::new(1, None);
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        starcoin_executor::validate_transaction_with_pool(&PanicStateView, txn.clone(), &pool)
+    }));
+    assert!(panicked.is_err());
+
+    // the only vm is returned to the pool, and validates the later txns.
+    assert!(pool.try_get().is_some());
+    assert!(starcoin_executor::validate_transaction_with_pool(&chain_state, txn, &pool).is_none());
+    Ok(())
+}
+
+#[stest::test]
+fn test_execute_transactions_with_events() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let account1 = Account::new();
+    let txns = vec![
+        Transaction::UserTransaction(create_account_txn_sent_as_association(
+            &account1,
+            get_sequence_number(account_config::association_address(), &chain_state),
+            50_000_000,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            &net,
+        )),
+        Transaction::UserTransaction(peer_to_peer_txn(
+            &account1,
+            &Account::new(),
+            0,
+            10_000,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            net.chain_id(),
+        )),
+    ];
+    let results = starcoin_executor::execute_transactions_with_events(&chain_state, txns, None)?;
+    assert_eq!(results.len(), 2);
+    for (output, events) in results {
+        assert_eq!(KeptVMStatus::Executed, output.status().status().unwrap());
+        assert!(!events.is_empty());
+        assert_eq!(events.as_slice(), output.events());
+    }
+    Ok(())
+}
+
+#[stest::test]
+fn test_block_execute_with_gas_used() -> Result<()> {
+    let (chain_state, net, account1) = prepare_funded_account();
+    let txns = transfer_txns(&account1, &net, 10);
+    let block_gas_limit = 3_000;
+    let (outputs, gas_used) = starcoin_executor::execute_block_transactions_with_gas_used(
+        &chain_state,
+        txns,
+        block_gas_limit,
+        None,
+    )?;
+    assert!(!outputs.is_empty() && outputs.len() < 10);
+    assert_eq!(
+        gas_used,
+        outputs.iter().map(|output| output.gas_used()).sum::<u64>()
+    );
+    assert!(gas_used > 0 && gas_used <= block_gas_limit);
+    Ok(())
+}
+
+#[stest::test]
+fn test_execute_and_apply_block() -> Result<()> {
+    let (chain_state, net) = prepare_genesis();
+    let account1 = Account::new();
+    let account2 = Account::new();
+    let expiration_timestamp_secs = net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME;
+    // the transfer from account1 depends on the account created by the prior txn.
+    let txns = vec![
+        Transaction::UserTransaction(create_account_txn_sent_as_association(
+            &account1,
+            get_sequence_number(account_config::association_address(), &chain_state),
+            50_000_000,
+            expiration_timestamp_secs,
+            &net,
+        )),
+        Transaction::UserTransaction(peer_to_peer_txn(
+            &account1,
+            &account2,
+            0,
+            10_000,
+            expiration_timestamp_secs,
+            net.chain_id(),
+        )),
+    ];
+    let outputs = starcoin_executor::execute_and_apply_block(&chain_state, txns, u64::MAX, None)?;
+    assert_eq!(outputs.len(), 2);
+    for output in &outputs {
+        assert_eq!(
+            output.status(),
+            &TransactionStatus::Keep(KeptVMStatus::Executed)
+        );
+    }
+    // the write sets are applied to the chain state.
+    assert_eq!(get_sequence_number(*account1.address(), &chain_state), 1);
+    assert_eq!(get_balance(*account2.address(), &chain_state), 10_000);
+    Ok(())
+}
+
+#[stest::test]
+fn test_reset_vm() -> Result<()> {
+    let account1 = Account::new();
+    let module_source = |value: u64| {
+        format!(
+            r#"
+        module {{{{sender}}}}::A {{
+            public fun value(): u64 {{ {} }}
+        }}
+        "#,
+            value
+        )
+    };
+    // the same module of account1 returns a different value in each state.
+    let prepare_state = |value: u64| -> Result<_> {
+        let (chain_state, net) = prepare_genesis();
+        let txn = Transaction::UserTransaction(create_account_txn_sent_as_association(
+            &account1,
+            get_sequence_number(account_config::association_address(), &chain_state),
+            50_000_000,
+            net.time_service().now_secs() + DEFAULT_EXPIRATION_TIME,
+            &net,
+        ));
+        execute_and_apply(&chain_state, txn);
+        let module = compile_modules_with_address(*account1.address(), &module_source(value))
+            .pop()
+            .unwrap();
+        account_execute_should_success(
+            &account1,
+            &chain_state,
+            TransactionPayload::Package(Package::new_with_module(module)?),
+        )?;
+        Ok(chain_state)
+    };
+    let old_state = prepare_state(1)?;
+    let new_state = prepare_state(2)?;
+
+    let vm = Arc::new(Mutex::new(StarcoinVM::new(None)));
+    let module_id = ModuleId::new(*account1.address(), Identifier::new("A").unwrap());
+    let function_name = Identifier::new("value").unwrap();
+    let call = |chain_state: &dyn StateView| -> Result<u64> {
+        let mut result = starcoin_executor::execute_readonly_function_with_vm(
+            chain_state,
+            &module_id,
+            &function_name,
+            vec![],
+            vec![],
+            &vm,
+        )
+        .map_err(|status| anyhow!("execute readonly function failed: {:?}", status))?;
+        Ok(bcs_ext::from_bytes(result.pop().unwrap().as_slice())?)
+    };
+    assert_eq!(call(&old_state)?, 1);
+    // the state is replaced underneath the vm, the module is reloaded after the reset.
+    starcoin_executor::reset_vm(&vm);
+    assert_eq!(call(&new_state)?, 2);
+    Ok(())
+}
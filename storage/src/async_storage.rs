@@ -0,0 +1,133 @@
+// Copyright (c) The Starcoin Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::batch::WriteBatch;
+use crate::cache_storage::CacheStorage;
+use crate::storage::InnerStore;
+use anyhow::Result;
+use std::sync::Arc;
+
+/// The async version of [`InnerStore`], for callers running on an async runtime,
+/// enabled by the `async` feature.
+#[async_trait::async_trait]
+pub trait AsyncInnerStore: Send + Sync {
+    async fn get(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+    async fn multi_get(
+        &self,
+        prefix_name: &str,
+        keys: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>>;
+    async fn put(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+    async fn contains_key(&self, prefix_name: &str, key: Vec<u8>) -> Result<bool>;
+    async fn remove(&self, prefix_name: &str, key: Vec<u8>) -> Result<()>;
+    async fn write_batch(&self, prefix_name: &str, batch: WriteBatch) -> Result<()>;
+}
+
+/// Adapt an [`InnerStore`] to [`AsyncInnerStore`], every call runs on the blocking thread pool
+/// by `spawn_blocking`, so the async worker threads are not blocked by the storage io.
+///
+/// It is intended for the RocksDB backed stores. For the pure cache backend the spawn overhead
+/// may outweigh the tiny shard lock, so [`CacheStorage`] implements [`AsyncInnerStore`] directly.
+pub struct SpawnBlockingStore<S> {
+    inner: Arc<S>,
+}
+
+impl<S> SpawnBlockingStore<S>
+where
+    S: InnerStore + 'static,
+{
+    pub fn new(inner: Arc<S>) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &Arc<S> {
+        &self.inner
+    }
+
+    async fn spawn<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&S) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || f(&inner)).await?
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> AsyncInnerStore for SpawnBlockingStore<S>
+where
+    S: InnerStore + 'static,
+{
+    async fn get(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let prefix_name = prefix_name.to_string();
+        self.spawn(move |store| store.get(&prefix_name, key)).await
+    }
+
+    async fn multi_get(
+        &self,
+        prefix_name: &str,
+        keys: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let prefix_name = prefix_name.to_string();
+        self.spawn(move |store| store.multi_get(&prefix_name, keys))
+            .await
+    }
+
+    async fn put(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let prefix_name = prefix_name.to_string();
+        self.spawn(move |store| store.put(&prefix_name, key, value))
+            .await
+    }
+
+    async fn contains_key(&self, prefix_name: &str, key: Vec<u8>) -> Result<bool> {
+        let prefix_name = prefix_name.to_string();
+        self.spawn(move |store| store.contains_key(&prefix_name, key))
+            .await
+    }
+
+    async fn remove(&self, prefix_name: &str, key: Vec<u8>) -> Result<()> {
+        let prefix_name = prefix_name.to_string();
+        self.spawn(move |store| store.remove(&prefix_name, key))
+            .await
+    }
+
+    async fn write_batch(&self, prefix_name: &str, batch: WriteBatch) -> Result<()> {
+        let prefix_name = prefix_name.to_string();
+        self.spawn(move |store| store.write_batch(&prefix_name, batch))
+            .await
+    }
+}
+
+/// The cache operations only touch the in memory shards, so they are called directly
+/// instead of spawning a blocking task.
+#[async_trait::async_trait]
+impl AsyncInnerStore for CacheStorage {
+    async fn get(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        InnerStore::get(self, prefix_name, key)
+    }
+
+    async fn multi_get(
+        &self,
+        prefix_name: &str,
+        keys: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        InnerStore::multi_get(self, prefix_name, keys)
+    }
+
+    async fn put(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        InnerStore::put(self, prefix_name, key, value)
+    }
+
+    async fn contains_key(&self, prefix_name: &str, key: Vec<u8>) -> Result<bool> {
+        InnerStore::contains_key(self, prefix_name, key)
+    }
+
+    async fn remove(&self, prefix_name: &str, key: Vec<u8>) -> Result<()> {
+        InnerStore::remove(self, prefix_name, key)
+    }
+
+    async fn write_batch(&self, prefix_name: &str, batch: WriteBatch) -> Result<()> {
+        InnerStore::write_batch(self, prefix_name, batch)
+    }
+}
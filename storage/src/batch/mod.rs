@@ -1,13 +1,16 @@
 // Copyright (c) The Starcoin Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::storage::{CodecWriteBatch, KeyCodec, ValueCodec, WriteOp};
-use anyhow::Result;
+use crate::storage::{CodecWriteBatch, ColumnFamilyName, KeyCodec, ValueCodec, WriteOp};
+use anyhow::{ensure, Result};
+use std::collections::HashSet;
 use std::convert::TryFrom;
 
 #[derive(Debug, Default, Clone)]
 pub struct WriteBatch {
     pub rows: Vec<(Vec<u8>, WriteOp<Vec<u8>>)>,
+    /// The max rows of the batch, unbounded if `None`.
+    max_rows: Option<usize>,
 }
 
 impl WriteBatch {
@@ -17,21 +20,116 @@ impl WriteBatch {
     }
 
     pub fn new_with_rows(rows: Vec<(Vec<u8>, WriteOp<Vec<u8>>)>) -> Self {
-        Self { rows }
+        Self {
+            rows,
+            max_rows: None,
+        }
+    }
+
+    /// Creates an empty batch holding at most `max_rows` rows,
+    /// adding more rows returns an error instead of growing unbounded.
+    pub fn with_limit(max_rows: usize) -> Self {
+        Self {
+            rows: Vec::new(),
+            max_rows: Some(max_rows),
+        }
+    }
+
+    /// Adds an operation to the batch, return an error if the batch is full.
+    pub fn push_checked(&mut self, key: Vec<u8>, write_op: WriteOp<Vec<u8>>) -> Result<()> {
+        if let Some(max_rows) = self.max_rows {
+            ensure!(
+                self.rows.len() < max_rows,
+                "write batch exceeds the max rows {}",
+                max_rows
+            );
+        }
+        self.rows.push((key, write_op));
+        Ok(())
     }
 
     /// Adds an insert/update operation to the batch.
     pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        self.rows.push((key, WriteOp::Value(value)));
-        Ok(())
+        self.push_checked(key, WriteOp::Value(value))
     }
 
     /// Adds a delete operation to the batch.
     pub fn delete(&mut self, key: Vec<u8>) -> Result<()> {
-        self.rows.push((key, WriteOp::Deletion));
+        self.push_checked(key, WriteOp::Deletion)
+    }
+
+    /// Adds a merge operation to the batch, see [`WriteOp::Merge`].
+    pub fn merge(&mut self, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        self.push_checked(key, WriteOp::Merge(operand))
+    }
+
+    /// Appends the rows of `other` after the rows of the batch, return an error if the batch
+    /// can not hold them. The rows are written in order, so for a key in both batches
+    /// the operation of `other` wins, e.g. a deletion in `other` supersedes a value in the batch.
+    pub fn append(&mut self, other: WriteBatch) -> Result<()> {
+        if let Some(max_rows) = self.max_rows {
+            ensure!(
+                self.rows.len() + other.rows.len() <= max_rows,
+                "write batch exceeds the max rows {}",
+                max_rows
+            );
+        }
+        self.rows.extend(other.rows);
         Ok(())
     }
 
+    /// Concatenates the rows of `batches` in order into one unbounded batch,
+    /// for a key in several batches the operation of the last batch wins, same as [`Self::append`].
+    pub fn concat(batches: Vec<WriteBatch>) -> WriteBatch {
+        let rows = batches.into_iter().flat_map(|batch| batch.rows).collect();
+        WriteBatch::new_with_rows(rows)
+    }
+
+    /// The count of rows in the batch.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The total bytes of the keys and values in the batch, a deletion only counts its key.
+    pub fn byte_size(&self) -> usize {
+        self.rows
+            .iter()
+            .map(|(key, write_op)| {
+                key.len()
+                    + match write_op {
+                        WriteOp::Value(value) | WriteOp::Merge(value) => value.len(),
+                        WriteOp::Deletion => 0,
+                    }
+            })
+            .sum()
+    }
+
+    /// Collapse the repeated keys, keep the last operation of every key.
+    /// A merge depends on the operations before it, so the operations before a merge are kept.
+    pub fn dedup(&mut self) {
+        let mut keep = {
+            let mut overwritten = HashSet::new();
+            self.rows
+                .iter()
+                .rev()
+                .map(|(key, write_op)| {
+                    let keep = !overwritten.contains(key.as_slice());
+                    if !matches!(write_op, WriteOp::Merge(_)) {
+                        overwritten.insert(key.as_slice());
+                    }
+                    keep
+                })
+                .collect::<Vec<_>>()
+        };
+        keep.reverse();
+        let mut keep = keep.into_iter();
+        self.rows.retain(|_| keep.next().unwrap_or(true));
+    }
+
     ///Clear all operation to the next batch.
     pub fn clear(&mut self) -> Result<()> {
         self.rows.clear();
@@ -39,6 +137,28 @@ impl WriteBatch {
     }
 }
 
+/// The write batches of multiple prefixes, written together by `InnerStore::write_batch_group`.
+#[derive(Debug, Default, Clone)]
+pub struct WriteBatchGroup {
+    pub batches: Vec<(ColumnFamilyName, WriteBatch)>,
+}
+
+impl WriteBatchGroup {
+    /// Creates an empty group.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the batch of `prefix_name` to the group.
+    pub fn add(&mut self, prefix_name: ColumnFamilyName, batch: WriteBatch) {
+        self.batches.push((prefix_name, batch));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+}
+
 impl<K, V> TryFrom<CodecWriteBatch<K, V>> for WriteBatch
 where
     K: KeyCodec,
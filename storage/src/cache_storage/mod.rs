@@ -2,30 +2,610 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::batch::WriteBatch;
-use crate::metrics::{record_metrics, StorageMetrics};
-use crate::storage::{InnerStore, WriteOp};
-use anyhow::{Error, Result};
-use lru::LruCache;
-use parking_lot::Mutex;
+use crate::metrics::{record_metrics, record_sampled_metrics, StorageMetrics};
+use crate::storage::{InnerStore, MergeOperator, WriteOp};
+use anyhow::{ensure, Error, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use logger::prelude::*;
+use parking_lot::RwLock;
 use starcoin_config::DEFAULT_CACHE_SIZE;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod shard_lru_cache;
+
+pub use shard_lru_cache::{
+    CacheLookup, EvictCallback, EvictionPolicy, FnvBuildHasher, ShardLruCache, DEFAULT_SHARD_BITS,
+    ENTRY_OVERHEAD, MAX_SHARD_BITS,
+};
+
+/// The fraction of the entries evicted by [`CacheStorage::on_memory_pressure`].
+pub const MEMORY_PRESSURE_EVICT_FRACTION: f64 = 0.25;
+
 pub struct CacheStorage {
-    cache: Mutex<LruCache<Vec<u8>, Vec<u8>>>,
+    /// The cache of the prefixes without their own budget.
+    cache: ShardLruCache,
+    /// The caches of the prefixes with their own budget, see [`CacheStorageBuilder::prefix_capacities`].
+    prefix_caches: BTreeMap<String, ShardLruCache>,
+    merge_operators: RwLock<HashMap<String, MergeOperator>>,
+    metrics: Option<StorageMetrics>,
+}
+
+/// Builder of [`CacheStorage`], the options not set are the same as `CacheStorage::new`.
+#[derive(Default)]
+pub struct CacheStorageBuilder {
+    capacity: Option<usize>,
+    shard_bits: Option<usize>,
+    ttl: Option<Duration>,
+    eviction_policy: EvictionPolicy,
+    prefix_capacities: HashMap<String, usize>,
     metrics: Option<StorageMetrics>,
 }
 
+impl CacheStorageBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The max count of entries, `DEFAULT_CACHE_SIZE` by default.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// The cache is split into `1 << shard_bits` shards, `DEFAULT_SHARD_BITS` by default.
+    pub fn shard_bits(mut self, shard_bits: usize) -> Self {
+        self.shard_bits = Some(shard_bits);
+        self
+    }
+
+    /// The entries expire after `ttl` if it is set, never expire by default.
+    pub fn ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// The policy choosing the entry to evict, [`EvictionPolicy::Lru`] by default.
+    /// The prefixes with their own caches use the same policy.
+    pub fn eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// Give the prefixes their own caches with the capacities, so the entries of a prefix are
+    /// never evicted by the entries of other prefixes. The other prefixes share the `capacity`.
+    pub fn prefix_capacities(mut self, prefix_capacities: HashMap<String, usize>) -> Self {
+        self.prefix_capacities = prefix_capacities;
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Option<StorageMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn build(self) -> Result<CacheStorage> {
+        let shard_bits = self.shard_bits.unwrap_or(DEFAULT_SHARD_BITS);
+        let cache = ShardLruCache::with_policy(
+            self.capacity.unwrap_or(DEFAULT_CACHE_SIZE),
+            shard_bits,
+            self.ttl,
+            self.eviction_policy,
+        )?;
+        let mut storage = CacheStorage::new_with_cache(cache, self.metrics);
+        for (prefix_name, capacity) in self.prefix_capacities {
+            let cache =
+                ShardLruCache::with_policy(capacity, shard_bits, self.ttl, self.eviction_policy)?;
+            storage.prefix_caches.insert(prefix_name, cache);
+        }
+        Ok(storage)
+    }
+}
+
 impl CacheStorage {
+    pub fn builder() -> CacheStorageBuilder {
+        CacheStorageBuilder::new()
+    }
     pub fn new(metrics: Option<StorageMetrics>) -> Self {
-        CacheStorage {
-            cache: Mutex::new(LruCache::new(DEFAULT_CACHE_SIZE)),
-            metrics,
-        }
+        Self::builder()
+            .metrics(metrics)
+            .build()
+            .expect("default cache storage options must be valid")
     }
     pub fn new_with_capacity(size: usize, metrics: Option<StorageMetrics>) -> Self {
+        Self::builder()
+            .capacity(size)
+            .metrics(metrics)
+            .build()
+            .expect("default shard bits must be valid")
+    }
+    /// Create a cache storage with `1 << shard_bits` shards.
+    pub fn new_with_shards(
+        size: usize,
+        shard_bits: usize,
+        metrics: Option<StorageMetrics>,
+    ) -> Result<Self> {
+        Self::builder()
+            .capacity(size)
+            .shard_bits(shard_bits)
+            .metrics(metrics)
+            .build()
+    }
+    /// Create a cache storage whose entries expire after `ttl`.
+    pub fn new_with_ttl(size: usize, ttl: Duration, metrics: Option<StorageMetrics>) -> Self {
+        Self::builder()
+            .capacity(size)
+            .ttl(Some(ttl))
+            .metrics(metrics)
+            .build()
+            .expect("default shard bits must be valid")
+    }
+    /// Create a cache storage where every prefix in `prefix_budgets` has its own cache of the
+    /// capacity, the other prefixes share a cache of `default_budget`.
+    /// So a prefix with many cold entries does not evict the entries of a hot prefix.
+    pub fn new_with_prefix_budgets(
+        prefix_budgets: HashMap<String, usize>,
+        default_budget: usize,
+        metrics: Option<StorageMetrics>,
+    ) -> Self {
+        Self::builder()
+            .capacity(default_budget)
+            .prefix_capacities(prefix_budgets)
+            .metrics(metrics)
+            .build()
+            .expect("default shard bits must be valid")
+    }
+    /// Create a cache storage bounded by the total bytes of keys and values,
+    /// see [`ShardLruCache::new_with_byte_limit`].
+    pub fn new_with_byte_limit(total_bytes: usize, metrics: Option<StorageMetrics>) -> Self {
+        Self::new_with_cache(ShardLruCache::new_with_byte_limit(total_bytes), metrics)
+    }
+    fn new_with_cache(cache: ShardLruCache, metrics: Option<StorageMetrics>) -> Self {
         CacheStorage {
-            cache: Mutex::new(LruCache::new(size)),
+            cache,
+            prefix_caches: BTreeMap::new(),
+            merge_operators: RwLock::new(HashMap::new()),
             metrics,
         }
     }
+    /// Call `on_evict` with the composed key and value of every entry evicted by the lru policy,
+    /// see [`ShardLruCache::set_on_evict`].
+    pub fn with_on_evict(mut self, on_evict: EvictCallback) -> Self {
+        self.cache.set_on_evict(on_evict.clone());
+        for cache in self.prefix_caches.values_mut() {
+            cache.set_on_evict(on_evict.clone());
+        }
+        self
+    }
+    /// Enable the negative cache, so the misses recorded by [`CacheStorage::put_absent`] are
+    /// answered by [`CacheStorage::lookup`] as known absent, see [`ShardLruCache::set_negative_cache`].
+    pub fn with_negative_cache(mut self, enabled: bool) -> Self {
+        self.cache.set_negative_cache(enabled);
+        for cache in self.prefix_caches.values_mut() {
+            cache.set_negative_cache(enabled);
+        }
+        self
+    }
+    /// Look up the value of `key` under `prefix_name`, a key recorded by `put_absent` is
+    /// [`CacheLookup::Absent`] instead of a [`CacheLookup::Miss`].
+    pub fn lookup(&self, prefix_name: &str, key: Vec<u8>) -> Result<CacheLookup> {
+        record_metrics("cache", prefix_name, "lookup", self.metrics.as_ref()).call(|| {
+            let lookup = with_composed_key(prefix_name, &key, |key| {
+                self.cache_of(prefix_name).lookup(key)
+            });
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.record_cache_lookup(
+                    prefix_name,
+                    "lookup",
+                    !matches!(lookup, CacheLookup::Miss),
+                );
+            }
+            Ok(lookup)
+        })
+    }
+    /// The write epoch of `key` under `prefix_name`, read before querying the backend for
+    /// a missed key, see [`ShardLruCache::write_epoch`].
+    pub fn write_epoch(&self, prefix_name: &str, key: &[u8]) -> u64 {
+        with_composed_key(prefix_name, key, |key| {
+            self.cache_of(prefix_name).write_epoch(key)
+        })
+    }
+    /// Record that `key` under `prefix_name` is absent from the backend, `epoch` is the
+    /// `write_epoch` read before the query. It is a no-op unless the negative cache is enabled,
+    /// or if the key may have been put since, see [`ShardLruCache::put_absent`].
+    /// A later put or remove of the key drops the record.
+    pub fn put_absent(&self, prefix_name: &str, key: Vec<u8>, epoch: u64) -> Result<()> {
+        self.cache_of(prefix_name)
+            .put_absent(compose_key(prefix_name, key), epoch);
+        Ok(())
+    }
+    /// The cache holding the entries of `prefix_name`.
+    fn cache_of(&self, prefix_name: &str) -> &ShardLruCache {
+        if self.prefix_caches.is_empty() {
+            return &self.cache;
+        }
+        self.prefix_caches.get(prefix_name).unwrap_or(&self.cache)
+    }
+    /// The cache holding the entry of the composed `key`.
+    fn cache_of_composed(&self, key: &[u8]) -> &ShardLruCache {
+        if self.prefix_caches.is_empty() {
+            return &self.cache;
+        }
+        decode_prefix(key).map_or(&self.cache, |prefix_name| self.cache_of(prefix_name))
+    }
+    /// All the caches, the shared cache first, then the prefix caches ordered by prefix name.
+    fn caches(&self) -> impl Iterator<Item = &ShardLruCache> + '_ {
+        std::iter::once(&self.cache).chain(self.prefix_caches.values())
+    }
+    fn entry_count(&self) -> usize {
+        self.caches().map(ShardLruCache::entry_count).sum()
+    }
+    fn update_cache_items(&self) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.cache_items.set(self.entry_count() as u64);
+        }
+    }
+    /// Register the operator applied to the [`WriteOp::Merge`] of `prefix_name`,
+    /// replace the operator registered before.
+    pub fn register_merge_operator(&self, prefix_name: &str, operator: MergeOperator) {
+        self.merge_operators
+            .write()
+            .insert(prefix_name.to_string(), operator);
+    }
+    /// Merge `operand` into the value of `key`, the shard is locked once for the read and the put.
+    /// Without a registered operator, the operand overwrites the current value.
+    pub fn merge(&self, prefix_name: &str, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        let operator = self.merge_operators.read().get(prefix_name).cloned();
+        let cache = self.cache_of(prefix_name);
+        let key = compose_key(prefix_name, key);
+        match operator {
+            Some(operator) => cache.merge(key, &operand, &*operator),
+            None => {
+                warn!(
+                    "No merge operator registered for {}, overwrite the value.",
+                    prefix_name
+                );
+                cache.put(key, operand);
+            }
+        }
+        self.update_cache_items();
+        Ok(())
+    }
+    /// The count of entries of every shard, to find the skew of the shards.
+    /// With prefix caches, the shards of the shared cache come first, then the shards of
+    /// the prefix caches ordered by prefix name.
+    pub fn shard_lens(&self) -> Vec<usize> {
+        self.caches().flat_map(ShardLruCache::shard_lens).collect()
+    }
+    /// The total bytes of keys and values in cache.
+    pub fn byte_len(&self) -> usize {
+        self.caches().map(ShardLruCache::byte_len).sum()
+    }
+    /// The approximate memory used by the entries, see [`ShardLruCache::memory_usage`].
+    pub fn memory_usage(&self) -> u64 {
+        self.caches().map(ShardLruCache::memory_usage).sum()
+    }
+    /// The bytes prepended to every key under `prefix_name` when the key is composed:
+    /// the varint length of the prefix name followed by the name.
+    pub fn prefix_overhead(prefix_name: &str) -> usize {
+        encode_prefix(prefix_name).len()
+    }
+    /// The approximate memory of an entry under `prefix_name` with a `key_len` bytes key and
+    /// a `value_len` bytes value, the composed key plus [`ENTRY_OVERHEAD`], the same as it is
+    /// counted by [`Self::memory_usage`]. Divide a memory budget by it to size the capacity.
+    pub fn effective_entry_size(prefix_name: &str, key_len: usize, value_len: usize) -> usize {
+        Self::prefix_overhead(prefix_name) + key_len + value_len + ENTRY_OVERHEAD
+    }
+    /// Get the value of `key` under `prefix_name`, or `None` if absent, the replacement of
+    /// `contains_key` followed by `get`, the key is composed and hashed only once.
+    pub fn get_if_present(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let cache = self.cache_of(prefix_name);
+        let value = with_composed_key(prefix_name, &key, |key| cache.get(key));
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.record_cache_lookup(prefix_name, "get_if_present", value.is_some());
+        }
+        Ok(value)
+    }
+    /// Get the value of a key composed by [`ComposedKey::new`].
+    pub fn get_composed(&self, key: &ComposedKey) -> Option<Vec<u8>> {
+        self.cache_of_composed(&key.0).get(&key.0)
+    }
+    /// Put the value of a key composed by [`ComposedKey::new`].
+    pub fn put_composed(&self, key: ComposedKey, value: Vec<u8>) {
+        self.cache_of_composed(&key.0).put(key.0, value);
+        self.update_cache_items();
+    }
+    /// Remove a key composed by [`ComposedKey::new`], return the removed value.
+    pub fn remove_composed(&self, key: &ComposedKey) -> Option<Vec<u8>> {
+        let prev = self.cache_of_composed(&key.0).pop(&key.0);
+        self.update_cache_items();
+        prev
+    }
+    /// Get the value of `key` under `prefix_name`, or put the value computed by `f` if absent.
+    /// `f` runs while the shard lock is held, so it must not access the cache again.
+    pub fn get_or_insert_with<F>(&self, prefix_name: &str, key: Vec<u8>, f: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        let value = self
+            .cache_of(prefix_name)
+            .get_or_insert_with(compose_key(prefix_name, key), f);
+        self.update_cache_items();
+        Ok(value)
+    }
+    /// Export all the entries in cache, which can be imported by `import` after restart.
+    /// The shards are locked one by one to avoid blocking all the cache operations.
+    pub fn export(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.caches().flat_map(ShardLruCache::entries).collect()
+    }
+    /// Take a read only snapshot of the entries, every shard is locked once.
+    /// The snapshot is consistent within a shard, but not across shards,
+    /// the writes to the cache after `snapshot` returns are not visible in the snapshot.
+    ///
+    /// The values are shared with the cache rather than copied, so a snapshot costs a copy of
+    /// the keys plus a map entry per entry. But a value overwritten or evicted in the cache
+    /// is kept alive by the snapshot until the snapshot is dropped.
+    pub fn snapshot(&self) -> CacheSnapshot {
+        CacheSnapshot {
+            entries: self
+                .caches()
+                .flat_map(ShardLruCache::shared_entries)
+                .collect(),
+        }
+    }
+    /// Import the entries exported by `export`, the least recently used entries
+    /// are evicted if the entries exceed the capacity.
+    pub fn import(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) {
+        if self.prefix_caches.is_empty() {
+            self.cache.put_all(entries);
+        } else {
+            for (key, value) in entries {
+                self.cache_of_composed(&key).put(key, value);
+            }
+        }
+        self.update_cache_items();
+    }
+    /// Write all the entries in cache to `writer` as a snapshot, which can be loaded by `load_from`
+    /// after restart. The snapshot starts with a version byte, followed by the entries, every entry
+    /// is the key length and value length as u32, the key, the value and a checksum of them.
+    /// Return the count of dumped entries.
+    pub fn dump_to<W: Write>(&self, mut writer: W) -> Result<usize> {
+        let entries = self.export();
+        writer.write_u8(SNAPSHOT_VERSION)?;
+        for (key, value) in &entries {
+            writer.write_u32::<BigEndian>(key.len() as u32)?;
+            writer.write_u32::<BigEndian>(value.len() as u32)?;
+            writer.write_all(key)?;
+            writer.write_all(value)?;
+            writer.write_u32::<BigEndian>(snapshot_checksum(key, value))?;
+        }
+        writer.flush()?;
+        Ok(entries.len())
+    }
+    /// Load the snapshot written by `dump_to`, return the count of loaded entries.
+    /// The entries with a mismatched checksum are skipped, a truncated or corrupted
+    /// entry length stops the loading, the entries before it are kept.
+    pub fn load_from<R: Read>(&self, mut reader: R) -> Result<usize> {
+        let version = reader.read_u8()?;
+        ensure!(
+            version == SNAPSHOT_VERSION,
+            "Unsupported cache snapshot version {}",
+            version
+        );
+        let mut loaded = 0;
+        let mut skipped = 0;
+        loop {
+            match read_snapshot_entry(&mut reader) {
+                Ok(Some((key, value, true))) => {
+                    self.cache_of_composed(&key).put(key, value);
+                    loaded += 1;
+                }
+                Ok(Some((_, _, false))) => skipped += 1,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Stop loading the corrupted cache snapshot: {:?}", e);
+                    break;
+                }
+            }
+        }
+        if skipped > 0 {
+            warn!("Skip {} corrupted entries of the cache snapshot.", skipped);
+        }
+        self.update_cache_items();
+        Ok(loaded)
+    }
+    /// Put all the entries under `prefix_name`, every shard is locked at most once and
+    /// `cache_items` is updated once at the end. A later entry of the same key overrides the earlier ones.
+    pub fn put_all(&self, prefix_name: &str, entries: Vec<(Vec<u8>, Vec<u8>)>) {
+        self.cache_of(prefix_name).put_all(
+            entries
+                .into_iter()
+                .map(|(key, value)| (compose_key(prefix_name, key), value))
+                .collect(),
+        );
+        self.update_cache_items();
+    }
+    /// Bulk insert the known hot entries under `prefix_name` before serving, e.g. at startup,
+    /// see [`Self::put_all`].
+    pub fn preload(&self, prefix_name: &str, entries: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        self.put_all(prefix_name, entries);
+        Ok(())
+    }
+    /// Warm the cache before a latency sensitive work, e.g. with the accounts a block touches:
+    /// call `loader` with every source key under `prefix_name` absent in cache, e.g. a DB read,
+    /// and put the loaded value. The present keys do not call `loader`, see [`ShardLruCache::prefetch`].
+    /// Return the count of the put entries.
+    pub fn prefetch<F>(&self, prefix_name: &str, keys: Vec<Vec<u8>>, loader: F) -> usize
+    where
+        F: Fn(&[u8]) -> Option<Vec<u8>>,
+    {
+        let prefix_len = encode_prefix(prefix_name).len();
+        let loaded = self.cache_of(prefix_name).prefetch(
+            keys.into_iter()
+                .map(|key| compose_key(prefix_name, key))
+                .collect(),
+            |key| loader(&key[prefix_len..]),
+        );
+        self.update_cache_items();
+        loaded
+    }
+    /// Call `f` with every composed key in cache, see [`ShardLruCache::for_each_key`].
+    /// `f` runs while the shard lock is held, so it must not access the cache again.
+    pub fn for_each_key<F>(&self, mut f: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        for cache in self.caches() {
+            cache.for_each_key(&mut f);
+        }
+    }
+    /// The source keys under `prefix_name`, i.e. the keys passed to `put`.
+    pub fn keys_with_prefix(&self, prefix_name: &str) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .cache_of(prefix_name)
+            .keys_with_prefix(&encode_prefix(prefix_name)))
+    }
+    /// Remove all the entries, e.g. after a state rollback invalidates every cached value.
+    pub fn clear(&self) {
+        for cache in self.caches() {
+            cache.clear();
+        }
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.cache_items.set(self.entry_count() as u64);
+            metrics.cache_bytes.set(self.memory_usage());
+        }
+    }
+    /// Shrink the cache voluntarily when the memory watcher reports memory pressure, the least
+    /// recently used [`MEMORY_PRESSURE_EVICT_FRACTION`] of the entries are evicted,
+    /// see [`ShardLruCache::evict_fraction`]. Return the count of evicted entries.
+    pub fn on_memory_pressure(&self) -> usize {
+        let evicted = self
+            .caches()
+            .map(|cache| cache.evict_fraction(MEMORY_PRESSURE_EVICT_FRACTION))
+            .sum();
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.cache_items.set(self.entry_count() as u64);
+            metrics.cache_bytes.set(self.memory_usage());
+        }
+        evicted
+    }
+    /// Remove all the entries under `prefix_name`, return the count of removed entries.
+    pub fn remove_prefix(&self, prefix_name: &str) -> Result<u64> {
+        let prefix = encode_prefix(prefix_name);
+        let removed = self
+            .cache_of(prefix_name)
+            .remove_if(|key| key.starts_with(&prefix));
+        self.update_cache_items();
+        Ok(removed as u64)
+    }
+    /// Replace all the entries under `prefix_name` by `entries`, e.g. after the column family is
+    /// rebuilt by the state sync. The replacement is atomic per shard, not across the shards,
+    /// and a key in both the old and the new entries is never transiently missing,
+    /// see [`ShardLruCache::replace_prefix`].
+    pub fn replace_prefix(
+        &self,
+        prefix_name: &str,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let result = record_metrics(
+            "cache",
+            prefix_name,
+            "replace_prefix",
+            self.metrics.as_ref(),
+        )
+        .call(|| {
+            self.cache_of(prefix_name).replace_prefix(
+                &encode_prefix(prefix_name),
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (compose_key(prefix_name, key), value))
+                    .collect(),
+            );
+            Ok(())
+        });
+        self.update_cache_items();
+        result
+    }
+}
+
+/// A key composed with its prefix name, which can be reused across
+/// [`CacheStorage::get_composed`], [`CacheStorage::put_composed`] and [`CacheStorage::remove_composed`]
+/// to avoid composing the key for every access.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ComposedKey(Vec<u8>);
+
+impl ComposedKey {
+    pub fn new(prefix_name: &str, key: Vec<u8>) -> Self {
+        Self(compose_key(prefix_name, key))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+/// An immutable view of the cache entries at a point in time, see [`CacheStorage::snapshot`].
+#[derive(Clone, Debug, Default)]
+pub struct CacheSnapshot {
+    entries: HashMap<Vec<u8>, Arc<Vec<u8>>>,
+}
+
+impl CacheSnapshot {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the value of `key` under `prefix_name` when the snapshot is taken.
+    pub fn get(&self, prefix_name: &str, key: &[u8]) -> Option<&[u8]> {
+        with_composed_key(prefix_name, key, |key| {
+            self.entries.get(key).map(|value| value.as_slice())
+        })
+    }
+
+    pub fn contains(&self, prefix_name: &str, key: &[u8]) -> bool {
+        with_composed_key(prefix_name, key, |key| self.entries.contains_key(key))
+    }
+
+    /// The keys under `prefix_name`, in no particular order.
+    pub fn keys(&self, prefix_name: &str) -> Vec<Vec<u8>> {
+        let prefix = encode_prefix(prefix_name);
+        self.entries
+            .keys()
+            .filter(|key| key.starts_with(&prefix))
+            .map(|key| key[prefix.len()..].to_vec())
+            .collect()
+    }
+
+    /// Iterate the entries with composed keys, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> + '_ {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+}
+
+impl IntoIterator for CacheSnapshot {
+    type Item = (Vec<u8>, Vec<u8>);
+    type IntoIter = std::iter::Map<
+        std::collections::hash_map::IntoIter<Vec<u8>, Arc<Vec<u8>>>,
+        fn((Vec<u8>, Arc<Vec<u8>>)) -> (Vec<u8>, Vec<u8>),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        fn unshare((key, value): (Vec<u8>, Arc<Vec<u8>>)) -> (Vec<u8>, Vec<u8>) {
+            let value = Arc::try_unwrap(value).unwrap_or_else(|value| value.as_ref().clone());
+            (key, value)
+        }
+        self.entries.into_iter().map(unshare as fn(_) -> _)
+    }
 }
 
 impl Default for CacheStorage {
@@ -37,81 +617,350 @@ impl Default for CacheStorage {
 impl InnerStore for CacheStorage {
     fn get(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
         record_metrics("cache", prefix_name, "get", self.metrics.as_ref()).call(|| {
-            Ok(self
-                .cache
-                .lock()
-                .get(&compose_key(prefix_name.to_string(), key))
-                .cloned())
+            let cache = self.cache_of(prefix_name);
+            let value = with_composed_key(prefix_name, &key, |key| cache.get(key));
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.record_cache_lookup(prefix_name, "get", value.is_some());
+            }
+            Ok(value)
+        })
+    }
+
+    fn get_arc(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Arc<Vec<u8>>>> {
+        record_metrics("cache", prefix_name, "get_arc", self.metrics.as_ref()).call(|| {
+            let cache = self.cache_of(prefix_name);
+            let value = with_composed_key(prefix_name, &key, |key| cache.get_arc(key));
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.record_cache_lookup(prefix_name, "get_arc", value.is_some());
+            }
+            Ok(value)
+        })
+    }
+
+    /// Read under the shard lock without promoting the entry or recording a cache lookup,
+    /// so sampling the cache for diagnostics perturbs neither the eviction order nor the hit rate.
+    fn peek(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let cache = self.cache_of(prefix_name);
+        Ok(with_composed_key(prefix_name, &key, |key| cache.peek(key)))
+    }
+
+    fn multi_get(&self, prefix_name: &str, keys: Vec<Vec<u8>>) -> Result<Vec<Option<Vec<u8>>>> {
+        record_metrics("cache", prefix_name, "multi_get", self.metrics.as_ref()).call(|| {
+            let composed_keys: Vec<Vec<u8>> = keys
+                .into_iter()
+                .map(|key| compose_key(prefix_name, key))
+                .collect();
+            let values = self.cache_of(prefix_name).multi_get(&composed_keys);
+            if let Some(metrics) = self.metrics.as_ref() {
+                for value in &values {
+                    metrics.record_cache_lookup(prefix_name, "multi_get", value.is_some());
+                }
+            }
+            Ok(values)
         })
     }
 
     fn put(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        // remove record_metrics for performance
-        // record_metrics add in write_batch to reduce Instant::now system call
-        let mut cache = self.cache.lock();
-        cache.put(compose_key(prefix_name.to_string(), key), value);
-        if let Some(metrics) = self.metrics.as_ref() {
-            metrics.cache_items.set(cache.len() as u64);
-        }
+        // the full record_metrics is too costly for the hot put, only sampled calls are timed.
+        record_sampled_metrics("cache", prefix_name, "put", self.metrics.as_ref()).call(|| {
+            self.cache_of(prefix_name)
+                .put(compose_key(prefix_name, key), value);
+        });
+        self.update_cache_items();
         Ok(())
     }
 
+    /// The previous value is returned by the same put, the shard is locked only once.
+    fn put_returning(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        let prev = self
+            .cache_of(prefix_name)
+            .put(compose_key(prefix_name, key), value);
+        self.update_cache_items();
+        Ok(prev)
+    }
+
+    fn put_if_absent(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        let inserted = self
+            .cache_of(prefix_name)
+            .put_if_absent(compose_key(prefix_name, key), value);
+        self.update_cache_items();
+        Ok(inserted)
+    }
+
+    fn compare_and_swap(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        let swapped = self.cache_of(prefix_name).compare_and_swap(
+            compose_key(prefix_name, key),
+            expected.as_deref(),
+            new,
+        );
+        self.update_cache_items();
+        Ok(swapped)
+    }
+
     fn contains_key(&self, prefix_name: &str, key: Vec<u8>) -> Result<bool> {
         record_metrics("cache", prefix_name, "contains_key", self.metrics.as_ref()).call(|| {
-            Ok(self
-                .cache
-                .lock()
-                .contains(&compose_key(prefix_name.to_string(), key)))
+            let cache = self.cache_of(prefix_name);
+            let exist = with_composed_key(prefix_name, &key, |key| cache.contains(key));
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.record_cache_lookup(prefix_name, "contains_key", exist);
+            }
+            Ok(exist)
+        })
+    }
+
+    fn multi_contains(&self, prefix_name: &str, keys: Vec<Vec<u8>>) -> Result<Vec<bool>> {
+        record_metrics(
+            "cache",
+            prefix_name,
+            "multi_contains",
+            self.metrics.as_ref(),
+        )
+        .call(|| {
+            let composed_keys: Vec<Vec<u8>> = keys
+                .into_iter()
+                .map(|key| compose_key(prefix_name, key))
+                .collect();
+            let exists = self.cache_of(prefix_name).multi_contains(&composed_keys);
+            if let Some(metrics) = self.metrics.as_ref() {
+                for exist in &exists {
+                    metrics.record_cache_lookup(prefix_name, "multi_contains", *exist);
+                }
+            }
+            Ok(exists)
         })
     }
     fn remove(&self, prefix_name: &str, key: Vec<u8>) -> Result<()> {
-        // remove record_metrics for performance
-        // record_metrics add in write_batch to reduce Instant::now system call
-        let mut cache = self.cache.lock();
-        cache.pop(&compose_key(prefix_name.to_string(), key));
-        if let Some(metrics) = self.metrics.as_ref() {
-            metrics.cache_items.set(cache.len() as u64);
-        }
-        Ok(())
+        self.remove_returning(prefix_name, key).map(|_| ())
+    }
+
+    fn remove_returning(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        // the full record_metrics is too costly for the hot remove, only sampled calls are timed.
+        let cache = self.cache_of(prefix_name);
+        let prev = record_sampled_metrics("cache", prefix_name, "remove", self.metrics.as_ref())
+            .call(|| with_composed_key(prefix_name, &key, |key| cache.pop(key)));
+        self.update_cache_items();
+        Ok(prev)
     }
 
-    fn write_batch(&self, prefix_name: &str, batch: WriteBatch) -> Result<()> {
+    fn write_batch(&self, prefix_name: &str, mut batch: WriteBatch) -> Result<()> {
         record_metrics("cache", prefix_name, "write_batch", self.metrics.as_ref()).call(|| {
+            batch.dedup();
             for (key, write_op) in &batch.rows {
                 match write_op {
                     WriteOp::Value(value) => self.put(prefix_name, key.to_vec(), value.to_vec())?,
                     WriteOp::Deletion => self.remove(prefix_name, key.to_vec())?,
+                    WriteOp::Merge(operand) => {
+                        self.merge(prefix_name, key.to_vec(), operand.to_vec())?
+                    }
                 };
             }
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.cache_bytes.set(self.memory_usage());
+            }
             Ok(())
         })
     }
 
+    fn write_batch_multi(&self, batches: Vec<(&str, WriteBatch)>) -> Result<()> {
+        // merges read the current value, apply the batches in order to keep the merge result.
+        if batches.iter().any(|(_, batch)| {
+            batch
+                .rows
+                .iter()
+                .any(|(_, write_op)| matches!(write_op, WriteOp::Merge(_)))
+        }) {
+            for (prefix_name, batch) in batches {
+                self.write_batch(prefix_name, batch)?;
+            }
+            return Ok(());
+        }
+        // the writes of the prefixes in the same cache are written together.
+        let mut cache_writes: Vec<(&ShardLruCache, Vec<(Vec<u8>, Option<Vec<u8>>)>)> = vec![];
+        for (prefix_name, batch) in batches {
+            let cache = self.cache_of(prefix_name);
+            let writes = batch.rows.into_iter().map(|(key, write_op)| {
+                let value = match write_op {
+                    WriteOp::Value(value) => Some(value),
+                    WriteOp::Deletion => None,
+                    WriteOp::Merge(_) => unreachable!("merges are written by write_batch"),
+                };
+                (compose_key(prefix_name, key), value)
+            });
+            match cache_writes
+                .iter_mut()
+                .find(|(written, _)| std::ptr::eq(*written, cache))
+            {
+                Some((_, cache_writes)) => cache_writes.extend(writes),
+                None => cache_writes.push((cache, writes.collect())),
+            }
+        }
+        for (cache, writes) in cache_writes {
+            cache.write_batch(writes);
+        }
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.cache_items.set(self.entry_count() as u64);
+            metrics.cache_bytes.set(self.memory_usage());
+        }
+        Ok(())
+    }
+
+    /// The entries are sorted by key, but the result is best-effort,
+    /// only the entries still in cache are returned, and concurrent writes may be missed
+    /// since the shards are scanned one by one.
+    fn scan_prefix(&self, prefix_name: &str, start: Vec<u8>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = self
+            .cache_of(prefix_name)
+            .entries_with_prefix(&encode_prefix(prefix_name))
+            .into_iter()
+            .filter(|(key, _)| key >= &start)
+            .collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entries)
+    }
+
+    /// The entries are in no particular order since the keys are hash-sharded, and only the
+    /// entries still in cache are returned, the shards are scanned one by one.
+    fn scan_key_prefix(
+        &self,
+        prefix_name: &str,
+        key_prefix: Vec<u8>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let prefix = compose_key(prefix_name, key_prefix.clone());
+        Ok(self
+            .cache_of(prefix_name)
+            .entries_with_prefix(&prefix)
+            .into_iter()
+            .map(|(key_suffix, value)| ([key_prefix.as_slice(), &key_suffix].concat(), value))
+            .collect())
+    }
+
+    /// Read from the running counts without locking the shards, see [`ShardLruCache::entry_count`].
     fn get_len(&self) -> Result<u64, Error> {
-        Ok(self.cache.lock().len() as u64)
+        Ok(self.entry_count() as u64)
     }
 
     fn keys(&self) -> Result<Vec<Vec<u8>>, Error> {
-        let mut all_keys = vec![];
-        for (key, _) in self.cache.lock().iter() {
-            all_keys.push(key.to_vec());
-        }
-        Ok(all_keys)
+        Ok(self.caches().flat_map(ShardLruCache::keys).collect())
     }
 
+    /// The cache has nothing to flush, the sync is a no-op and it is the same as `put`.
+    /// A `StorageInstance::CacheAndDb` forwards the sync to the db, not to the cache.
     fn put_sync(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
         self.put(prefix_name, key, value)
     }
 
+    /// Same as `write_batch`, see `put_sync`.
     fn write_batch_sync(&self, prefix_name: &str, batch: WriteBatch) -> Result<()> {
         self.write_batch(prefix_name, batch)
     }
 }
 
-fn compose_key(prefix_name: String, source_key: Vec<u8>) -> Vec<u8> {
-    let temp_vec = prefix_name.as_bytes().to_vec();
-    let mut compose = Vec::with_capacity(temp_vec.len() + source_key.len());
-    compose.extend(temp_vec);
+const SNAPSHOT_VERSION: u8 = 1;
+/// The max bytes of an entry in the snapshot, a larger length means the snapshot is corrupted.
+const MAX_SNAPSHOT_ENTRY_BYTES: usize = 64 * 1024 * 1024;
+
+fn snapshot_checksum(key: &[u8], value: &[u8]) -> u32 {
+    let mut hasher = FnvBuildHasher.build_hasher();
+    hasher.write(key);
+    hasher.write(value);
+    hasher.finish() as u32
+}
+
+/// Read an entry of the snapshot, return `None` at the end of the snapshot,
+/// the bool is whether the checksum matches.
+fn read_snapshot_entry<R: Read>(reader: &mut R) -> Result<Option<(Vec<u8>, Vec<u8>, bool)>> {
+    let key_len = match reader.read_u32::<BigEndian>() {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let value_len = reader.read_u32::<BigEndian>()? as usize;
+    ensure!(
+        key_len + value_len <= MAX_SNAPSHOT_ENTRY_BYTES,
+        "Invalid cache snapshot entry length {}",
+        key_len + value_len
+    );
+    let mut key = vec![0u8; key_len];
+    reader.read_exact(&mut key)?;
+    let mut value = vec![0u8; value_len];
+    reader.read_exact(&mut value)?;
+    let checksum = reader.read_u32::<BigEndian>()?;
+    let valid = checksum == snapshot_checksum(&key, &value);
+    Ok(Some((key, value, valid)))
+}
+
+/// Encode the prefix name as its varint length followed by its bytes,
+/// so the encoded prefix of a name is never a byte prefix of another name's.
+fn encode_prefix(prefix_name: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(prefix_name.len() + 2);
+    write_prefix(&mut prefix, prefix_name);
+    prefix
+}
+
+/// Decode the prefix name of a composed key, return `None` if the key is not composed.
+fn decode_prefix(key: &[u8]) -> Option<&str> {
+    let mut len = 0usize;
+    for (index, byte) in key.iter().enumerate().take(4) {
+        len |= ((byte & 0x7f) as usize) << (7 * index);
+        if byte & 0x80 == 0 {
+            let name = key.get(index + 1..index + 1 + len)?;
+            return std::str::from_utf8(name).ok();
+        }
+    }
+    None
+}
+
+fn write_prefix(buf: &mut Vec<u8>, prefix_name: &str) {
+    let name = prefix_name.as_bytes();
+    let mut len = name.len();
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+    buf.extend_from_slice(name);
+}
+
+/// Compose the key with only one allocation, sized for a prefix name length of up to 2 bytes.
+fn compose_key(prefix_name: &str, source_key: Vec<u8>) -> Vec<u8> {
+    let mut compose = Vec::with_capacity(prefix_name.len() + 2 + source_key.len());
+    write_prefix(&mut compose, prefix_name);
     compose.extend(source_key);
     compose
 }
+
+thread_local! {
+    static COMPOSE_KEY_BUF: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+}
+
+/// Call `f` with the composed key in a thread local buffer, so the lookups do not allocate.
+/// The buffer is reused and only grows, it keeps the capacity of the longest key composed on
+/// the thread, so only a key longer than all before allocates.
+/// `f` must not compose another key with `with_composed_key`.
+fn with_composed_key<R, F>(prefix_name: &str, source_key: &[u8], f: F) -> R
+where
+    F: FnOnce(&[u8]) -> R,
+{
+    COMPOSE_KEY_BUF.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        buf.clear();
+        write_prefix(&mut buf, prefix_name);
+        buf.extend_from_slice(source_key);
+        f(buf.as_slice())
+    })
+}
@@ -0,0 +1,1431 @@
+// Copyright (c) The Starcoin Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{ensure, Result};
+use lru::LruCache;
+use parking_lot::{Mutex, MutexGuard};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default shard bits, 16 shards.
+pub const DEFAULT_SHARD_BITS: usize = 4;
+/// Max shard bits, 4096 shards, more shards only add per shard overhead.
+pub const MAX_SHARD_BITS: usize = 12;
+
+/// The approximate bytes of an entry besides its key and value: the key and value vectors,
+/// the entry with the `Arc` counters, and the links of the lru node.
+pub const ENTRY_OVERHEAD: usize =
+    2 * size_of::<Vec<u8>>() + size_of::<CacheEntry>() + 4 * size_of::<usize>();
+
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Called with the key and value of every entry evicted by the lru policy,
+/// see [`ShardLruCache::set_on_evict`].
+pub type EvictCallback = Arc<dyn Fn(&[u8], &[u8]) + Send + Sync>;
+
+/// The result of [`ShardLruCache::lookup`], which tells the keys known absent from the keys
+/// the cache knows nothing about.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CacheLookup {
+    /// The key is cached with the value.
+    Value(Vec<u8>),
+    /// The key is recorded as absent by [`ShardLruCache::put_absent`].
+    Absent,
+    /// The key is not cached.
+    Miss,
+}
+
+/// The default hasher to distribute the keys to the shards, 32 bits FNV-1a,
+/// which is much faster than SipHash for short keys.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FnvBuildHasher;
+
+impl BuildHasher for FnvBuildHasher {
+    type Hasher = FnvHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        FnvHasher(FNV_OFFSET_BASIS)
+    }
+}
+
+/// The hasher built by [`FnvBuildHasher`].
+pub struct FnvHasher(u32);
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        u64::from(self.0)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |hash, byte| {
+            (hash ^ u32::from(*byte)).wrapping_mul(FNV_PRIME)
+        });
+    }
+}
+
+struct CacheEntry {
+    value: Arc<Vec<u8>>,
+    expire_at: Option<Instant>,
+}
+
+impl CacheEntry {
+    fn new(value: Vec<u8>, ttl: Option<Duration>) -> Self {
+        Self {
+            value: Arc::new(value),
+            expire_at: ttl.map(|ttl| Instant::now() + ttl),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expire_at
+            .map_or(false, |expire_at| expire_at <= Instant::now())
+    }
+
+    fn into_value(self) -> Vec<u8> {
+        Arc::try_unwrap(self.value).unwrap_or_else(|value| value.as_ref().clone())
+    }
+}
+
+/// The policy choosing the entry a full cache evicts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used entry.
+    Lru,
+    /// Evict the earliest put entry, a hit does not reorder the entries,
+    /// an overwrite puts the entry again.
+    Fifo,
+    /// Evict the least frequently used entry, the earliest put one among the entries of the
+    /// same frequency. The frequency counts the puts and hits of the key since it is put, so the
+    /// keys read only once by a scan are evicted before the hot keys, but a long idle hot key
+    /// keeps its frequency. The frequency index holds two more copies of every key, which are
+    /// not counted in the memory usage.
+    Lfu,
+    /// The segmented lru, see [`ShardLruCache::new_segmented`].
+    Segmented { protected_ratio: f64 },
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::Lru
+    }
+}
+
+/// How a shard orders the entries for eviction.
+enum ShardOrder {
+    /// By the recency of puts and hits, the lru and segmented lru policies.
+    Recency,
+    /// By the order of puts only, the fifo policy.
+    Insertion,
+    /// By the frequency then the order of puts, the lfu policy.
+    Frequency(FrequencyIndex),
+}
+
+impl ShardOrder {
+    /// An order of the same kind without any entry.
+    fn empty(&self) -> Self {
+        match self {
+            ShardOrder::Recency => ShardOrder::Recency,
+            ShardOrder::Insertion => ShardOrder::Insertion,
+            ShardOrder::Frequency(_) => ShardOrder::Frequency(FrequencyIndex::default()),
+        }
+    }
+}
+
+/// The keys of a lfu shard ordered by their rank, the frequency then the put sequence.
+#[derive(Default)]
+struct FrequencyIndex {
+    ranks: HashMap<Vec<u8>, (u64, u64)>,
+    order: BTreeMap<(u64, u64), Vec<u8>>,
+    seq: u64,
+}
+
+impl FrequencyIndex {
+    fn frequency(&self, key: &[u8]) -> u64 {
+        self.ranks.get(key).map_or(0, |(frequency, _)| *frequency)
+    }
+
+    fn set(&mut self, key: Vec<u8>, frequency: u64) {
+        self.remove(&key);
+        self.seq += 1;
+        let rank = (frequency, self.seq);
+        self.order.insert(rank, key.clone());
+        self.ranks.insert(key, rank);
+    }
+
+    fn hit(&mut self, key: &[u8]) {
+        if let Some(&(frequency, _)) = self.ranks.get(key) {
+            self.set(key.to_vec(), frequency.saturating_add(1));
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(rank) = self.ranks.remove(key) {
+            self.order.remove(&rank);
+        }
+    }
+
+    /// The frequency of the key `pop_least_frequent` pops next, 0 if there is no key.
+    fn least_frequency(&self) -> u64 {
+        self.order
+            .keys()
+            .next()
+            .map_or(0, |(frequency, _)| *frequency)
+    }
+
+    fn pop_least_frequent(&mut self) -> Option<Vec<u8>> {
+        let rank = *self.order.keys().next()?;
+        let key = self.order.remove(&rank)?;
+        self.ranks.remove(&key);
+        Some(key)
+    }
+
+    fn clear(&mut self) {
+        self.ranks.clear();
+        self.order.clear();
+    }
+}
+
+/// The running totals of all the shards, read without locking the shards.
+#[derive(Default)]
+struct CacheUsage {
+    bytes: AtomicUsize,
+    entries: AtomicUsize,
+}
+
+impl CacheUsage {
+    fn add(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn sub(&self, bytes: usize) {
+        self.sub_entries(bytes, 1);
+    }
+
+    fn sub_entries(&self, bytes: usize, entries: usize) {
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+        self.entries.fetch_sub(entries, Ordering::Relaxed);
+    }
+}
+
+/// One shard of the cache, tracks the bytes of the keys and values it holds.
+///
+/// A segmented shard keeps two lru segments: new entries enter `cache` as the probationary
+/// segment, and an entry hit again is promoted to the `protected` segment. The least recently
+/// used protected entry is demoted back to the probationary segment once `protected` is full,
+/// so only the probationary entries are evicted and a scan of cold keys does not flush the hot ones.
+///
+/// A fifo or lfu shard only uses `cache` as the storage, the hits do not update its lru order,
+/// and the lfu shard evicts by its own frequency index.
+struct LruShard {
+    cache: LruCache<Vec<u8>, CacheEntry>,
+    protected: Option<LruCache<Vec<u8>, CacheEntry>>,
+    order: ShardOrder,
+    bytes: usize,
+    byte_limit: Option<usize>,
+    usage: Arc<CacheUsage>,
+    /// The entries evicted by the lru policy, only tracked if there is an evict callback.
+    evicted: Option<Vec<(Vec<u8>, Arc<Vec<u8>>)>>,
+    /// The tombstones of the keys known absent with their expire time, only tracked if the
+    /// negative cache is enabled. A key never has both an entry and a tombstone, every put or
+    /// pop of the key drops its tombstone.
+    absent: Option<LruCache<Vec<u8>, Option<Instant>>>,
+    /// Moved by every put, so a miss read from the backend before a put is not recorded
+    /// as a tombstone after it, see [`ShardLruCache::write_epoch`].
+    epoch: u64,
+}
+
+impl LruShard {
+    fn new(cap: usize, usage: Arc<CacheUsage>) -> Self {
+        Self {
+            cache: LruCache::new(cap),
+            protected: None,
+            order: ShardOrder::Recency,
+            bytes: 0,
+            byte_limit: None,
+            usage,
+            evicted: None,
+            absent: None,
+            epoch: 0,
+        }
+    }
+
+    /// Create a segmented shard, `protected_cap` of the `cap` entries are reserved for the
+    /// protected segment. Both segments need at least one entry, else a plain shard is created.
+    fn new_segmented(cap: usize, protected_cap: usize, usage: Arc<CacheUsage>) -> Self {
+        if protected_cap == 0 || protected_cap >= cap {
+            return Self::new(cap, usage);
+        }
+        Self {
+            protected: Some(LruCache::new(protected_cap)),
+            ..Self::new(cap - protected_cap, usage)
+        }
+    }
+
+    fn with_order(cap: usize, order: ShardOrder, usage: Arc<CacheUsage>) -> Self {
+        Self {
+            order,
+            ..Self::new(cap, usage)
+        }
+    }
+
+    fn with_byte_limit(byte_limit: usize, usage: Arc<CacheUsage>) -> Self {
+        Self {
+            cache: LruCache::unbounded(),
+            protected: None,
+            order: ShardOrder::Recency,
+            bytes: 0,
+            byte_limit: Some(byte_limit),
+            usage,
+            evicted: None,
+            absent: None,
+            epoch: 0,
+        }
+    }
+
+    /// An empty shard with the same policy, sized for `new_shards` shards sharing the budget
+    /// of `old_shards` shards like this one.
+    fn resized(&self, old_shards: usize, new_shards: usize, usage: Arc<CacheUsage>) -> Self {
+        let share = |budget: usize| (budget * old_shards + new_shards - 1) / new_shards;
+        let mut shard = match (self.byte_limit, self.protected.as_ref()) {
+            (Some(byte_limit), _) => Self::with_byte_limit(share(byte_limit), usage),
+            (None, Some(protected)) => Self::new_segmented(
+                share(self.cache.cap() + protected.cap()),
+                share(protected.cap()),
+                usage,
+            ),
+            (None, None) => Self::with_order(share(self.cache.cap()), self.order.empty(), usage),
+        };
+        shard.evicted = self.evicted.as_ref().map(|_| vec![]);
+        let absent_cap = shard.entry_cap();
+        if self.absent.is_some() && absent_cap > 0 {
+            shard.absent = Some(LruCache::new(absent_cap));
+        }
+        shard
+    }
+
+    fn len(&self) -> usize {
+        self.cache.len() + self.protected.as_ref().map_or(0, LruCache::len)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The max count of entries, estimated by the entry overhead for a shard bounded by bytes.
+    fn entry_cap(&self) -> usize {
+        match self.byte_limit {
+            Some(byte_limit) => byte_limit / ENTRY_OVERHEAD,
+            None => self.cache.cap() + self.protected.as_ref().map_or(0, LruCache::cap),
+        }
+    }
+
+    /// Look up `key` like `get`, but tell whether an absent key has a tombstone.
+    fn lookup(&mut self, key: &[u8]) -> CacheLookup {
+        if let Some(entry) = self.get(key) {
+            return CacheLookup::Value(entry.value.as_ref().clone());
+        }
+        let absent = match self.absent.as_mut() {
+            Some(absent) => absent,
+            None => return CacheLookup::Miss,
+        };
+        match absent.get(key) {
+            Some(expire_at) if expire_at.map_or(false, |expire_at| expire_at <= Instant::now()) => {
+                absent.pop(key);
+                CacheLookup::Miss
+            }
+            Some(_) => CacheLookup::Absent,
+            None => CacheLookup::Miss,
+        }
+    }
+
+    /// Record a tombstone of `key`, unless the negative cache is disabled, the shard is written
+    /// since `epoch`, or the key has an entry.
+    fn put_absent(&mut self, key: Vec<u8>, epoch: u64, ttl: Option<Duration>) -> bool {
+        if self.absent.is_none() || self.epoch != epoch || self.contains(&key) {
+            return false;
+        }
+        if let Some(absent) = self.absent.as_mut() {
+            absent.put(key, ttl.map(|ttl| Instant::now() + ttl));
+        }
+        true
+    }
+
+    /// Iterate the entries from the most recently used to the least recently used,
+    /// the protected entries come before the probationary ones. A fifo shard iterates from the
+    /// latest put entry, a lfu shard from the entry it evicts last.
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = (&Vec<u8>, &CacheEntry)> + '_> {
+        match &self.order {
+            ShardOrder::Frequency(index) => Box::new(
+                index
+                    .order
+                    .values()
+                    .rev()
+                    .filter_map(move |key| self.cache.peek(key).map(|entry| (key, entry))),
+            ),
+            _ => Box::new(
+                self.protected
+                    .iter()
+                    .flat_map(|protected| protected.iter())
+                    .chain(self.cache.iter()),
+            ),
+        }
+    }
+
+    /// Look up the entry of `key` without updating the lru order.
+    fn peek(&self, key: &[u8]) -> Option<&CacheEntry> {
+        self.cache.peek(key).or_else(|| {
+            self.protected
+                .as_ref()
+                .and_then(|protected| protected.peek(key))
+        })
+    }
+
+    fn is_protected(&self, key: &[u8]) -> bool {
+        self.protected
+            .as_ref()
+            .map_or(false, |protected| protected.contains(key))
+    }
+
+    /// Drop the entry of `key` if it is expired, return whether the entry is dropped.
+    fn pop_expired(&mut self, key: &[u8]) -> bool {
+        if self.peek(key).map_or(false, CacheEntry::is_expired) {
+            self.pop(key);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<&CacheEntry> {
+        if self.pop_expired(key) {
+            return None;
+        }
+        match &mut self.order {
+            ShardOrder::Insertion => return self.cache.peek(key),
+            ShardOrder::Frequency(index) => {
+                index.hit(key);
+                return self.cache.peek(key);
+            }
+            ShardOrder::Recency => {}
+        }
+        if self.protected.is_none() {
+            return self.cache.get(key);
+        }
+        if let Some(entry) = self.cache.pop(key) {
+            self.promote(key.to_vec(), entry);
+        }
+        self.protected
+            .as_mut()
+            .and_then(|protected| protected.get(key))
+    }
+
+    /// Move a probationary entry to the protected segment, the entry keeps its bytes accounted.
+    fn promote(&mut self, key: Vec<u8>, entry: CacheEntry) {
+        let protected = self
+            .protected
+            .as_mut()
+            .expect("only a segmented shard promotes entries");
+        if protected.len() >= protected.cap() {
+            // the probationary segment has room, as the promoted entry just left it.
+            if let Some((demoted_key, demoted)) = protected.pop_lru() {
+                self.cache.put(demoted_key, demoted);
+            }
+        }
+        protected.put(key, entry);
+    }
+
+    fn contains(&mut self, key: &[u8]) -> bool {
+        !self.pop_expired(key) && self.peek(key).is_some()
+    }
+
+    fn put(&mut self, key: Vec<u8>, entry: CacheEntry) -> Option<CacheEntry> {
+        self.put_with_frequency(key, entry, None)
+    }
+
+    /// Same as `put`, but a lfu shard sets the frequency of the key to `frequency` if it is
+    /// given, instead of counting the put as one more use of the key.
+    fn put_with_frequency(
+        &mut self,
+        key: Vec<u8>,
+        entry: CacheEntry,
+        frequency: Option<u64>,
+    ) -> Option<CacheEntry> {
+        self.epoch = self.epoch.wrapping_add(1);
+        if self.cache.cap() == 0 {
+            return None;
+        }
+        let protected = self.is_protected(&key);
+        let frequency = match &self.order {
+            ShardOrder::Frequency(index) => frequency.unwrap_or_else(|| index.frequency(&key) + 1),
+            _ => 0,
+        };
+        let prev = self.pop(&key);
+        if !protected && self.cache.len() >= self.cache.cap() {
+            self.evict_lru();
+        }
+        self.bytes += key.len() + entry.value.len();
+        self.usage.add(key.len() + entry.value.len());
+        match self.protected.as_mut() {
+            // an update of a protected entry stays protected, in the slot it just left.
+            Some(segment) if protected => {
+                segment.put(key, entry);
+            }
+            _ => {
+                if let ShardOrder::Frequency(index) = &mut self.order {
+                    // an overwrite counts as a use of the key.
+                    index.set(key.clone(), frequency);
+                }
+                self.cache.put(key, entry);
+            }
+        }
+        if let Some(byte_limit) = self.byte_limit {
+            // always keep the newest entry, even if it alone exceeds the byte limit.
+            while self.bytes > byte_limit && self.len() > 1 {
+                self.evict_lru();
+            }
+        }
+        prev
+    }
+
+    fn clear(&mut self) {
+        self.usage.sub_entries(self.bytes, self.len());
+        self.bytes = 0;
+        self.cache.clear();
+        if let Some(protected) = self.protected.as_mut() {
+            protected.clear();
+        }
+        if let ShardOrder::Frequency(index) = &mut self.order {
+            index.clear();
+        }
+        if let Some(absent) = self.absent.as_mut() {
+            absent.clear();
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        if let Some((key, entry)) = self.pop_lru() {
+            if let Some(evicted) = self.evicted.as_mut() {
+                evicted.push((key, entry.value));
+            }
+        }
+    }
+
+    fn take_evicted(&mut self) -> Vec<(Vec<u8>, Arc<Vec<u8>>)> {
+        self.evicted
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn pop(&mut self, key: &[u8]) -> Option<CacheEntry> {
+        // the key is written or removed, so the cache no longer knows it is absent.
+        if let Some(absent) = self.absent.as_mut() {
+            absent.pop(key);
+        }
+        let entry = match self.cache.pop(key) {
+            Some(entry) => Some(entry),
+            None => self
+                .protected
+                .as_mut()
+                .and_then(|protected| protected.pop(key)),
+        };
+        if let Some(entry) = entry.as_ref() {
+            self.bytes -= key.len() + entry.value.len();
+            self.usage.sub(key.len() + entry.value.len());
+            if let ShardOrder::Frequency(index) = &mut self.order {
+                index.remove(key);
+            }
+        }
+        entry
+    }
+
+    /// Pop the entry the policy evicts next: the least recently used probationary entry, or the
+    /// protected one if there is no probationary entry left. A fifo shard pops the earliest put
+    /// entry, and a lfu shard the least frequently used.
+    fn pop_lru(&mut self) -> Option<(Vec<u8>, CacheEntry)> {
+        let popped = match &mut self.order {
+            ShardOrder::Frequency(index) => index
+                .pop_least_frequent()
+                .and_then(|key| self.cache.pop(&key).map(|entry| (key, entry))),
+            _ => match self.cache.pop_lru() {
+                Some(popped) => Some(popped),
+                None => self.protected.as_mut().and_then(LruCache::pop_lru),
+            },
+        };
+        if let Some((key, entry)) = popped.as_ref() {
+            self.bytes -= key.len() + entry.value.len();
+            self.usage.sub(key.len() + entry.value.len());
+        }
+        popped
+    }
+
+    /// Same as `pop_lru`, but also return the frequency of the popped key, 0 unless it is lfu.
+    fn pop_lru_with_frequency(&mut self) -> Option<(Vec<u8>, CacheEntry, u64)> {
+        let frequency = match &self.order {
+            ShardOrder::Frequency(index) => index.least_frequency(),
+            _ => 0,
+        };
+        self.pop_lru().map(|(key, entry)| (key, entry, frequency))
+    }
+}
+
+/// A lru cache split into `1 << shard_bits` shards, every shard is guarded by its own lock,
+/// so concurrent access to different keys seldom contend on the same lock.
+/// The keys are distributed to the shards by the hash built by `S`.
+/// The cache evicts by lru unless another [`EvictionPolicy`] is chosen by [`Self::with_policy`].
+pub struct ShardLruCache<S = FnvBuildHasher> {
+    shards: Vec<Mutex<LruShard>>,
+    shard_bits: usize,
+    ttl: Option<Duration>,
+    usage: Arc<CacheUsage>,
+    hash_builder: S,
+    on_evict: Option<EvictCallback>,
+}
+
+impl ShardLruCache {
+    pub fn new(cap: usize) -> Self {
+        Self::new_with_bits(cap, DEFAULT_SHARD_BITS, FnvBuildHasher)
+    }
+
+    /// Create a cache whose entries are treated as absent once `ttl` elapsed since they were put,
+    /// the expired entries are dropped lazily when they are touched.
+    pub fn new_with_ttl(cap: usize, ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..Self::new(cap)
+        }
+    }
+
+    /// Create a cache bounded by the total bytes of keys and values instead of the entry count,
+    /// every shard evicts the least recently used entries once it exceeds its share of the bytes.
+    /// A single entry larger than the share of its shard is still kept, as the only entry of the shard.
+    pub fn new_with_byte_limit(total_bytes: usize) -> Self {
+        let num_shards = 1usize << DEFAULT_SHARD_BITS;
+        let per_shard_bytes = (total_bytes + num_shards - 1) / num_shards;
+        let usage = Arc::new(CacheUsage::default());
+        let shards = (0..num_shards)
+            .map(|_| Mutex::new(LruShard::with_byte_limit(per_shard_bytes, usage.clone())))
+            .collect();
+        Self {
+            shards,
+            shard_bits: DEFAULT_SHARD_BITS,
+            ttl: None,
+            usage,
+            hash_builder: FnvBuildHasher,
+            on_evict: None,
+        }
+    }
+
+    /// Create a cache with `1 << shard_bits` shards evicting by the `policy`,
+    /// whose entries expire after `ttl` if it is set.
+    pub fn with_policy(
+        cap: usize,
+        shard_bits: usize,
+        ttl: Option<Duration>,
+        policy: EvictionPolicy,
+    ) -> Result<Self> {
+        if let EvictionPolicy::Segmented { protected_ratio } = policy {
+            ensure!(
+                protected_ratio > 0.0 && protected_ratio < 1.0,
+                "protected ratio {} should be in (0, 1)",
+                protected_ratio
+            );
+        }
+        let mut cache = Self {
+            ttl,
+            ..Self::with_shards(cap, shard_bits)?
+        };
+        let per_shard_cap = (cap + cache.shards.len() - 1) / cache.shards.len();
+        let order = match policy {
+            EvictionPolicy::Lru => return Ok(cache),
+            EvictionPolicy::Fifo => ShardOrder::Insertion,
+            EvictionPolicy::Lfu => ShardOrder::Frequency(FrequencyIndex::default()),
+            EvictionPolicy::Segmented { protected_ratio } => {
+                let protected_cap = (per_shard_cap as f64 * protected_ratio) as usize;
+                for shard in &mut cache.shards {
+                    *shard.get_mut() =
+                        LruShard::new_segmented(per_shard_cap, protected_cap, cache.usage.clone());
+                }
+                return Ok(cache);
+            }
+        };
+        for shard in &mut cache.shards {
+            *shard.get_mut() =
+                LruShard::with_order(per_shard_cap, order.empty(), cache.usage.clone());
+        }
+        Ok(cache)
+    }
+
+    /// Create a cache with the segmented lru policy: an entry enters a probationary segment and
+    /// is promoted to a protected segment when it is hit again, `protected_ratio` of the entries
+    /// of every shard are reserved for the protected segment. So a scan of keys read only once
+    /// just cycles through the probationary segment, and does not evict the frequently read keys.
+    /// A shard too small to hold both segments falls back to the plain lru policy.
+    pub fn new_segmented(cap: usize, protected_ratio: f64) -> Result<Self> {
+        Self::with_policy(
+            cap,
+            DEFAULT_SHARD_BITS,
+            None,
+            EvictionPolicy::Segmented { protected_ratio },
+        )
+    }
+
+    /// Create a cache with `1 << shard_bits` shards, the `cap` is shared by all shards.
+    pub fn with_shards(cap: usize, shard_bits: usize) -> Result<Self> {
+        Self::with_hasher(cap, shard_bits, FnvBuildHasher)
+    }
+
+    /// Create a cache with `1 << shard_bits` shards, whose entries expire after `ttl` if it is set.
+    pub fn with_shards_and_ttl(
+        cap: usize,
+        shard_bits: usize,
+        ttl: Option<Duration>,
+    ) -> Result<Self> {
+        Self::with_policy(cap, shard_bits, ttl, EvictionPolicy::Lru)
+    }
+}
+
+impl<S> ShardLruCache<S>
+where
+    S: BuildHasher,
+{
+    /// Create a cache with `1 << shard_bits` shards, which distributes the keys to the shards
+    /// by the hash built by `hash_builder` instead of the default FNV-1a.
+    pub fn with_hasher(cap: usize, shard_bits: usize, hash_builder: S) -> Result<Self> {
+        ensure!(
+            shard_bits <= MAX_SHARD_BITS,
+            "shard bits {} should not be greater than {}",
+            shard_bits,
+            MAX_SHARD_BITS
+        );
+        Ok(Self::new_with_bits(cap, shard_bits, hash_builder))
+    }
+
+    fn new_with_bits(cap: usize, shard_bits: usize, hash_builder: S) -> Self {
+        let num_shards = 1usize << shard_bits;
+        let per_shard_cap = (cap + num_shards - 1) / num_shards;
+        let usage = Arc::new(CacheUsage::default());
+        let shards = (0..num_shards)
+            .map(|_| Mutex::new(LruShard::new(per_shard_cap, usage.clone())))
+            .collect();
+        Self {
+            shards,
+            shard_bits,
+            ttl: None,
+            usage,
+            hash_builder,
+            on_evict: None,
+        }
+    }
+
+    /// Call `on_evict` with the key and value of every entry evicted by the lru policy,
+    /// but not the removed or expired entries. `on_evict` runs after the shard lock is released,
+    /// so it is free to access the cache again. The evicted entries are only tracked once
+    /// the callback is set, a cache without callback pays nothing.
+    pub fn set_on_evict(&mut self, on_evict: EvictCallback) {
+        for shard in &mut self.shards {
+            shard.get_mut().evicted.get_or_insert_with(Vec::new);
+        }
+        self.on_evict = Some(on_evict);
+    }
+
+    /// Enable or disable the negative cache. Once enabled, [`ShardLruCache::put_absent`] records
+    /// the keys known absent as tombstones, so [`ShardLruCache::lookup`] can answer them without
+    /// asking the backend. Every shard keeps at most as many tombstones as entries, the
+    /// tombstones expire like the entries if the cache has a ttl, and any write of a key
+    /// drops its tombstone.
+    pub fn set_negative_cache(&mut self, enabled: bool) {
+        for shard in &mut self.shards {
+            let shard = shard.get_mut();
+            let cap = shard.entry_cap();
+            shard.absent = if enabled && cap > 0 {
+                Some(LruCache::new(cap))
+            } else {
+                None
+            };
+        }
+    }
+
+    pub fn shard_bits(&self) -> usize {
+        self.shard_bits
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Hash the key with the hash builder, the 64 bits hash is folded to 32 bits.
+    fn get_idx(&self, key: &[u8]) -> u32 {
+        let mut hasher = self.hash_builder.build_hasher();
+        hasher.write(key);
+        let hash = hasher.finish();
+        (hash ^ (hash >> 32)) as u32
+    }
+
+    fn shard(&self, hash: u32) -> usize {
+        if self.shard_bits > 0 {
+            (hash >> (32 - self.shard_bits)) as usize
+        } else {
+            0
+        }
+    }
+
+    /// The index of the shard the key belongs to.
+    pub(crate) fn shard_index(&self, key: &[u8]) -> usize {
+        self.shard(self.get_idx(key))
+    }
+
+    fn shard_of(&self, key: &[u8]) -> &Mutex<LruShard> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Lock the shard for a write which may evict entries.
+    fn lock_shard(&self, index: usize) -> ShardGuard<'_> {
+        ShardGuard {
+            guard: Some(self.shards[index].lock()),
+            on_evict: self.on_evict.as_ref(),
+        }
+    }
+
+    fn lock_shard_of(&self, key: &[u8]) -> ShardGuard<'_> {
+        self.lock_shard(self.shard_index(key))
+    }
+
+    /// Group the position of every key by its shard index.
+    fn group_by_shard<'a>(&self, keys: impl Iterator<Item = &'a [u8]>) -> Vec<Vec<usize>> {
+        let mut groups = vec![vec![]; self.shards.len()];
+        for (pos, key) in keys.enumerate() {
+            groups[self.shard_index(key)].push(pos);
+        }
+        groups
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.shard_of(key)
+            .lock()
+            .get(key)
+            .map(|entry| entry.value.as_ref().clone())
+    }
+
+    /// Look up the key like `get`, but tell the keys with a tombstone from the unknown keys.
+    pub fn lookup(&self, key: &[u8]) -> CacheLookup {
+        self.shard_of(key).lock().lookup(key)
+    }
+
+    /// The write epoch of the shard of `key`, read it before querying the backend for a missed
+    /// key and pass it to [`ShardLruCache::put_absent`].
+    pub fn write_epoch(&self, key: &[u8]) -> u64 {
+        self.shard_of(key).lock().epoch
+    }
+
+    /// Record that `key` is absent from the backend, return whether the tombstone is recorded.
+    /// `epoch` is the [`ShardLruCache::write_epoch`] read before the backend query. Nothing is
+    /// recorded if the negative cache is disabled, or the shard of the key has been put since
+    /// `epoch`: the put may have reached the backend after the query, even if its entry has been
+    /// evicted meanwhile. A put to another key of the shard also skips the tombstone, which is
+    /// only a missed chance to cache the absence.
+    pub fn put_absent(&self, key: Vec<u8>, epoch: u64) -> bool {
+        self.shard_of(&key).lock().put_absent(key, epoch, self.ttl)
+    }
+
+    /// Get the value without promoting the entry in the lru order, e.g. for diagnostics.
+    /// An expired entry is treated as absent, but it is not dropped.
+    pub fn peek(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.shard_of(key)
+            .lock()
+            .peek(key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.value.as_ref().clone())
+    }
+
+    /// Get the value without copying it, the returned value is shared with the cache.
+    pub fn get_arc(&self, key: &[u8]) -> Option<Arc<Vec<u8>>> {
+        self.shard_of(key)
+            .lock()
+            .get(key)
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Get the values of `keys`, every shard is locked at most once.
+    /// The result is in the same order as `keys`.
+    pub fn multi_get(&self, keys: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+        let mut values = vec![None; keys.len()];
+        let groups = self.group_by_shard(keys.iter().map(|key| key.as_slice()));
+        for (shard, positions) in self.shards.iter().zip(groups) {
+            if positions.is_empty() {
+                continue;
+            }
+            let mut shard = shard.lock();
+            for pos in positions {
+                values[pos] = shard
+                    .get(&keys[pos])
+                    .map(|entry| entry.value.as_ref().clone());
+            }
+        }
+        values
+    }
+
+    /// Load the values of the `keys` absent in cache by `loader` and put them, return the count
+    /// of the put entries. The keys are grouped by shard, every shard is locked once to find the
+    /// absent keys, and once more to put the loaded values. `loader` runs without the shard lock,
+    /// a key put by others while loading keeps its value, and a key `loader` returns `None` for
+    /// is left absent.
+    pub fn prefetch<F>(&self, mut keys: Vec<Vec<u8>>, loader: F) -> usize
+    where
+        F: Fn(&[u8]) -> Option<Vec<u8>>,
+    {
+        keys.sort();
+        keys.dedup();
+        let mut groups: Vec<Vec<Vec<u8>>> = (0..self.shards.len()).map(|_| vec![]).collect();
+        for key in keys {
+            groups[self.shard_index(&key)].push(key);
+        }
+        let mut loaded = 0;
+        for (index, keys) in groups.into_iter().enumerate() {
+            if keys.is_empty() {
+                continue;
+            }
+            let absent_keys: Vec<Vec<u8>> = {
+                let mut shard = self.shards[index].lock();
+                keys.into_iter()
+                    .filter(|key| !shard.contains(key))
+                    .collect()
+            };
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = absent_keys
+                .into_iter()
+                .filter_map(|key| loader(&key).map(|value| (key, value)))
+                .collect();
+            if entries.is_empty() {
+                continue;
+            }
+            let mut shard = self.lock_shard(index);
+            for (key, value) in entries {
+                if !shard.contains(&key) {
+                    shard.put(key, CacheEntry::new(value, self.ttl));
+                    loaded += 1;
+                }
+            }
+        }
+        loaded
+    }
+
+    /// Put the key value to cache, return the previous value of the key.
+    /// The entry never expires, unless the cache is created with a ttl.
+    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.insert(key, value, self.ttl)
+    }
+
+    /// Put the key value to cache, the entry is treated as absent once `ttl` elapsed.
+    pub fn put_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Option<Vec<u8>> {
+        self.insert(key, value, Some(ttl))
+    }
+
+    fn insert(&self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>) -> Option<Vec<u8>> {
+        self.lock_shard_of(&key)
+            .put(key, CacheEntry::new(value, ttl))
+            .filter(|entry| !entry.is_expired())
+            .map(CacheEntry::into_value)
+    }
+
+    /// Apply the writes, a `None` value removes the key, every shard is locked at most once.
+    /// The writes of the same key are in the same shard, so they are applied in order.
+    pub fn write_batch(&self, writes: Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        let mut groups = vec![vec![]; self.shards.len()];
+        for (key, value) in writes {
+            groups[self.shard_index(&key)].push((key, value));
+        }
+        for (index, writes) in groups.into_iter().enumerate() {
+            if writes.is_empty() {
+                continue;
+            }
+            let mut shard = self.lock_shard(index);
+            for (key, value) in writes {
+                match value {
+                    Some(value) => {
+                        shard.put(key, CacheEntry::new(value, self.ttl));
+                    }
+                    None => {
+                        shard.pop(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Put all the entries, every shard is locked at most once, see [`Self::write_batch`].
+    /// A later entry of the same key overrides the earlier ones.
+    pub fn put_all(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) {
+        self.write_batch(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key, Some(value)))
+                .collect(),
+        );
+    }
+
+    /// Get the value of `key`, or put the value computed by `f` if the key is absent,
+    /// the shard is locked only once, so no other writer can put the key in between.
+    /// `f` runs while the shard lock is held, so it must not access the cache again.
+    pub fn get_or_insert_with<F>(&self, key: Vec<u8>, f: F) -> Vec<u8>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        let mut shard = self.lock_shard_of(&key);
+        if let Some(entry) = shard.get(&key) {
+            return entry.value.as_ref().clone();
+        }
+        let value = f();
+        shard.put(key, CacheEntry::new(value.clone(), self.ttl));
+        value
+    }
+
+    /// Put the key value only if the key is absent, return whether the value is inserted.
+    /// The check and the put are done under one shard lock.
+    pub fn put_if_absent(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        let mut shard = self.lock_shard_of(&key);
+        if shard.contains(&key) {
+            return false;
+        }
+        shard.put(key, CacheEntry::new(value, self.ttl));
+        true
+    }
+
+    /// Put the value computed by `f` from the current value of `key` and `operand`,
+    /// the read and the put are done under one shard lock.
+    /// `f` runs while the shard lock is held, so it must not access the cache again.
+    pub fn merge<F>(&self, key: Vec<u8>, operand: &[u8], f: F)
+    where
+        F: FnOnce(Option<&[u8]>, &[u8]) -> Vec<u8>,
+    {
+        let mut shard = self.lock_shard_of(&key);
+        let merged = f(shard.get(&key).map(|entry| entry.value.as_slice()), operand);
+        shard.put(key, CacheEntry::new(merged, self.ttl));
+    }
+
+    /// Write `new` only if the current value equals `expected`, return whether the swap happened.
+    /// A missing key is treated as `None`, a `None` for `new` removes the key.
+    /// The compare and the write are done under one shard lock.
+    pub fn compare_and_swap(
+        &self,
+        key: Vec<u8>,
+        expected: Option<&[u8]>,
+        new: Option<Vec<u8>>,
+    ) -> bool {
+        let mut shard = self.lock_shard_of(&key);
+        if shard.get(&key).map(|entry| entry.value.as_slice()) != expected {
+            return false;
+        }
+        match new {
+            Some(value) => {
+                shard.put(key, CacheEntry::new(value, self.ttl));
+            }
+            None => {
+                shard.pop(&key);
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.shard_of(key).lock().contains(key)
+    }
+
+    /// Check the existence of `keys`, every shard is locked at most once.
+    /// The result is in the same order as `keys`.
+    pub fn multi_contains(&self, keys: &[Vec<u8>]) -> Vec<bool> {
+        let mut exists = vec![false; keys.len()];
+        let groups = self.group_by_shard(keys.iter().map(|key| key.as_slice()));
+        for (shard, positions) in self.shards.iter().zip(groups) {
+            if positions.is_empty() {
+                continue;
+            }
+            let mut shard = shard.lock();
+            for pos in positions {
+                exists[pos] = shard.contains(&keys[pos]);
+            }
+        }
+        exists
+    }
+
+    pub fn pop(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.shard_of(key)
+            .lock()
+            .pop(key)
+            .filter(|entry| !entry.is_expired())
+            .map(CacheEntry::into_value)
+    }
+
+    /// Remove all the entries whose key matches `predicate`, return the count of removed entries.
+    /// The shards are locked one by one.
+    pub fn remove_if<F>(&self, mut predicate: F) -> usize
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let mut removed = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock();
+            let matched_keys: Vec<Vec<u8>> = shard
+                .iter()
+                .filter(|(key, _)| predicate(key.as_slice()))
+                .map(|(key, _)| key.to_vec())
+                .collect();
+            for key in matched_keys {
+                if shard.pop(&key).is_some() {
+                    removed += 1;
+                }
+            }
+        }
+        removed
+    }
+
+    /// Replace all the entries whose key starts with `prefix` by `entries`, whose keys must
+    /// start with `prefix` too. A later entry of the same key overrides the earlier ones.
+    /// The replacement is atomic per shard: the shards are locked one by one, and a reader of
+    /// a shard sees either all the old entries of the shard or all the new ones. It is not atomic
+    /// across shards. A key in both the old and the new entries is overwritten in place, so it is
+    /// never transiently missing. The new entries may still be evicted if they exceed the capacity.
+    pub fn replace_prefix(&self, prefix: &[u8], entries: Vec<(Vec<u8>, Vec<u8>)>) {
+        let mut groups = vec![vec![]; self.shards.len()];
+        for (key, value) in entries {
+            groups[self.shard_index(&key)].push((key, value));
+        }
+        for (index, entries) in groups.into_iter().enumerate() {
+            let mut shard = self.lock_shard(index);
+            let stale_keys: Vec<Vec<u8>> = {
+                let new_keys: HashSet<&[u8]> =
+                    entries.iter().map(|(key, _)| key.as_slice()).collect();
+                shard
+                    .iter()
+                    .filter(|(key, _)| {
+                        key.starts_with(prefix) && !new_keys.contains(key.as_slice())
+                    })
+                    .map(|(key, _)| key.to_vec())
+                    .collect()
+            };
+            for key in stale_keys {
+                shard.pop(&key);
+            }
+            for (key, value) in entries {
+                shard.put(key, CacheEntry::new(value, self.ttl));
+            }
+        }
+    }
+
+    /// Remove all the entries, the shards are locked and cleared one by one,
+    /// so the entries put to the cleared shards during the clearing are kept.
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().clear();
+        }
+    }
+
+    /// Evict the least recently used `fraction` of the entries of every shard, rounded up,
+    /// e.g. to reclaim memory under memory pressure. The `fraction` is clamped to `[0.0, 1.0]`,
+    /// `0.0` evicts nothing. The evicted entries are passed to the evict callback if it is set.
+    /// Return the count of evicted entries.
+    pub fn evict_fraction(&self, fraction: f64) -> usize {
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        if fraction == 0.0 {
+            return 0;
+        }
+        let mut evicted = 0;
+        for index in 0..self.shards.len() {
+            let mut shard = self.lock_shard(index);
+            let count = (shard.len() as f64 * fraction).ceil() as usize;
+            for _ in 0..count {
+                shard.evict_lru();
+            }
+            evicted += count;
+        }
+        evicted
+    }
+
+    /// Drop all the expired entries, return the count of dropped entries.
+    pub fn purge_expired(&self) -> usize {
+        let mut purged = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock();
+            let expired_keys: Vec<Vec<u8>> = shard
+                .iter()
+                .filter(|(_, entry)| entry.is_expired())
+                .map(|(key, _)| key.to_vec())
+                .collect();
+            for key in expired_keys {
+                if shard.pop(&key).is_some() {
+                    purged += 1;
+                }
+            }
+        }
+        purged
+    }
+
+    /// Drain all the entries into a new cache of `1 << new_shard_bits` shards, every entry is
+    /// re-inserted into the shard its key belongs to with the new shard bits.
+    /// The new cache keeps the total budget, the ttl, the policy and the evict callback of this
+    /// cache, the budget is split evenly to the new shards. The keys may not spread as evenly as
+    /// the budget, so a new shard over its share evicts the entries its policy evicts first;
+    /// the entries of every old shard are re-inserted in the order the policy evicts them, so the
+    /// recently (or for lfu, frequently) used entries are kept. A lfu entry keeps its frequency,
+    /// the segmented entries restart in probation. The expired entries are dropped, the entries
+    /// put during the rebuild stay in this cache. Only the tombstone capacity of the negative
+    /// cache is kept, the tombstones are not carried over, the absent keys are just unknown to
+    /// the new cache.
+    pub fn rebuild_with_shards(&self, new_shard_bits: usize) -> Result<Self>
+    where
+        S: Clone,
+    {
+        ensure!(
+            new_shard_bits <= MAX_SHARD_BITS,
+            "shard bits {} should not be greater than {}",
+            new_shard_bits,
+            MAX_SHARD_BITS
+        );
+        let num_shards = 1usize << new_shard_bits;
+        let usage = Arc::new(CacheUsage::default());
+        let shards = {
+            let template = self.shards[0].lock();
+            (0..num_shards)
+                .map(|_| Mutex::new(template.resized(self.shards.len(), num_shards, usage.clone())))
+                .collect()
+        };
+        let rebuilt = Self {
+            shards,
+            shard_bits: new_shard_bits,
+            ttl: self.ttl,
+            usage,
+            hash_builder: self.hash_builder.clone(),
+            on_evict: self.on_evict.clone(),
+        };
+        for shard in &self.shards {
+            let mut drained = vec![];
+            {
+                let mut shard = shard.lock();
+                while let Some((key, entry, frequency)) = shard.pop_lru_with_frequency() {
+                    if !entry.is_expired() {
+                        drained.push((key, entry, frequency));
+                    }
+                }
+            }
+            let mut groups: Vec<Vec<_>> = (0..num_shards).map(|_| vec![]).collect();
+            for (key, entry, frequency) in drained {
+                groups[rebuilt.shard_index(&key)].push((key, entry, frequency));
+            }
+            for (index, entries) in groups.into_iter().enumerate() {
+                if entries.is_empty() {
+                    continue;
+                }
+                let mut shard = rebuilt.lock_shard(index);
+                for (key, entry, frequency) in entries {
+                    shard.put_with_frequency(key, entry, Some(frequency));
+                }
+            }
+        }
+        Ok(rebuilt)
+    }
+
+    /// The count of entries of every shard in the shard index order,
+    /// every shard is locked briefly one by one.
+    pub fn shard_lens(&self) -> Vec<usize> {
+        self.shards.iter().map(|shard| shard.lock().len()).collect()
+    }
+
+    /// The count of entries in cache, when some entries have ttl, it is an upper bound
+    /// because the expired entries not yet touched or purged are counted too.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    /// The count of entries read from the running total without locking the shards,
+    /// it counts the same entries as `len`, the cheap replacement for a hot path.
+    pub fn entry_count(&self) -> usize {
+        self.usage.entries.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().is_empty())
+    }
+
+    /// The total bytes of keys and values in cache.
+    pub fn byte_len(&self) -> usize {
+        self.usage.bytes.load(Ordering::Relaxed)
+    }
+
+    /// The approximate memory used by the entries, the bytes of keys and values
+    /// plus [`ENTRY_OVERHEAD`] per entry, read from the running totals without locking.
+    pub fn memory_usage(&self) -> u64 {
+        let entries = self.usage.entries.load(Ordering::Relaxed);
+        (self.byte_len() + entries * ENTRY_OVERHEAD) as u64
+    }
+
+    /// Same as `memory_usage`, but computed by scanning all the entries, the shards are locked one by one.
+    pub fn scan_memory_usage(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .lock()
+                    .iter()
+                    .map(|(key, entry)| (key.len() + entry.value.len() + ENTRY_OVERHEAD) as u64)
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+
+    /// All the not expired entries, the shards are locked one by one,
+    /// the entries of a shard are ordered from least recently used to most recently used.
+    pub fn entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries = vec![];
+        for shard in &self.shards {
+            let shard = shard.lock();
+            entries.extend(
+                shard
+                    .iter()
+                    .rev()
+                    .filter(|(_, entry)| !entry.is_expired())
+                    .map(|(key, entry)| (key.to_vec(), entry.value.as_ref().clone())),
+            );
+        }
+        entries
+    }
+
+    /// All the not expired entries in the order they would be evicted, to find what is about
+    /// to be evicted. The order is only kept within a shard, from the least recently used to
+    /// the most recently used, a segmented shard lists the probationary entries before the
+    /// protected ones, a fifo or lfu shard lists the entries in the order its policy evicts them.
+    /// There is no global lru order across the shards, the shards are merged by
+    /// rank: the least recently used entry of every shard comes first, then the second ones, etc.
+    /// The shards are locked one by one.
+    pub fn entries_lru_order(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let shard_entries: Vec<Vec<(Vec<u8>, Vec<u8>)>> = self
+            .shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .lock()
+                    .iter()
+                    .rev()
+                    .filter(|(_, entry)| !entry.is_expired())
+                    .map(|(key, entry)| (key.to_vec(), entry.value.as_ref().clone()))
+                    .collect()
+            })
+            .collect();
+        let mut shard_iters: Vec<_> = shard_entries.into_iter().map(Vec::into_iter).collect();
+        let mut entries = vec![];
+        loop {
+            let len = entries.len();
+            entries.extend(shard_iters.iter_mut().filter_map(Iterator::next));
+            if entries.len() == len {
+                return entries;
+            }
+        }
+    }
+
+    /// Same as `entries`, but the values are shared with the cache instead of copied.
+    pub fn shared_entries(&self) -> Vec<(Vec<u8>, Arc<Vec<u8>>)> {
+        let mut entries = vec![];
+        for shard in &self.shards {
+            let shard = shard.lock();
+            entries.extend(
+                shard
+                    .iter()
+                    .rev()
+                    .filter(|(_, entry)| !entry.is_expired())
+                    .map(|(key, entry)| (key.to_vec(), entry.value.clone())),
+            );
+        }
+        entries
+    }
+
+    /// Call `f` with every key in cache without copying the keys.
+    /// `f` runs while the shard lock is held, so it must not access the cache again.
+    pub fn for_each_key<F>(&self, mut f: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        for shard in &self.shards {
+            for (key, _) in shard.lock().iter() {
+                f(key.as_slice());
+            }
+        }
+    }
+
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        let mut all_keys = vec![];
+        self.for_each_key(|key| all_keys.push(key.to_vec()));
+        all_keys
+    }
+
+    /// The keys starting with `prefix`, with the `prefix` stripped.
+    pub fn keys_with_prefix(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        let mut keys = vec![];
+        self.for_each_key(|key| {
+            if key.starts_with(prefix) {
+                keys.push(key[prefix.len()..].to_vec());
+            }
+        });
+        keys
+    }
+
+    /// The entries whose key starts with `prefix`, with the prefix stripped from the keys.
+    /// The entries are in no particular order, the shards are locked one by one.
+    pub fn entries_with_prefix(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries = vec![];
+        for shard in &self.shards {
+            let shard = shard.lock();
+            entries.extend(
+                shard
+                    .iter()
+                    .filter(|(key, entry)| key.starts_with(prefix) && !entry.is_expired())
+                    .map(|(key, entry)| {
+                        (key[prefix.len()..].to_vec(), entry.value.as_ref().clone())
+                    }),
+            );
+        }
+        entries
+    }
+}
+
+/// The lock of a shard, the evict callback is called with the evicted entries after the lock is released.
+struct ShardGuard<'a> {
+    guard: Option<MutexGuard<'a, LruShard>>,
+    on_evict: Option<&'a EvictCallback>,
+}
+
+impl<'a> Deref for ShardGuard<'a> {
+    type Target = LruShard;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.as_ref().expect("guard must exist before drop")
+    }
+}
+
+impl<'a> DerefMut for ShardGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.as_mut().expect("guard must exist before drop")
+    }
+}
+
+impl<'a> Drop for ShardGuard<'a> {
+    fn drop(&mut self) {
+        if let Some(mut guard) = self.guard.take() {
+            let evicted = guard.take_evicted();
+            drop(guard);
+            if let Some(on_evict) = self.on_evict {
+                for (key, value) in evicted {
+                    on_evict(key.as_slice(), value.as_slice());
+                }
+            }
+        }
+    }
+}
@@ -6,7 +6,8 @@ use crate::errors::StorageInitError;
 use crate::metrics::{record_metrics, StorageMetrics};
 use crate::storage::{ColumnFamilyName, InnerStore, KeyCodec, ValueCodec, WriteOp};
 use crate::{StorageVersion, DEFAULT_PREFIX_NAME};
-use anyhow::{ensure, format_err, Error, Result};
+use anyhow::{bail, ensure, format_err, Error, Result};
+use parking_lot::Mutex;
 use rocksdb::{Options, ReadOptions, WriteBatch as DBWriteBatch, WriteOptions, DB};
 use starcoin_config::{check_open_fds_limit, RocksdbConfig};
 use std::collections::HashSet;
@@ -20,6 +21,8 @@ pub struct DBStorage {
     db: DB,
     cfs: Vec<ColumnFamilyName>,
     metrics: Option<StorageMetrics>,
+    /// Serialize the read-modify-write of `put_if_absent` and `compare_and_swap`.
+    read_modify_write_lock: Mutex<()>,
 }
 
 impl DBStorage {
@@ -99,6 +102,7 @@ impl DBStorage {
             db,
             cfs: column_families,
             metrics,
+            read_modify_write_lock: Mutex::new(()),
         })
     }
 
@@ -257,6 +261,65 @@ impl DBStorage {
         opts.set_sync(true);
         opts
     }
+
+    fn write_options(sync: bool) -> WriteOptions {
+        if sync {
+            Self::sync_write_options()
+        } else {
+            Self::default_write_options()
+        }
+    }
+
+    /// `put` and `put_sync` differ only by the `sync` of the write options,
+    /// a synced write returns after the WAL is fsynced.
+    fn put_with_sync(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        sync: bool,
+    ) -> Result<()> {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics
+                .storage_item_bytes
+                .with_label_values(&[prefix_name])
+                .observe((key.len() + value.len()) as f64);
+        }
+
+        let method = if sync { "put_sync" } else { "put" };
+        record_metrics("db", prefix_name, method, self.metrics.as_ref()).call(|| {
+            let cf_handle = self.get_cf_handle(prefix_name)?;
+            self.db
+                .put_cf_opt(cf_handle, &key, &value, &Self::write_options(sync))?;
+            Ok(())
+        })
+    }
+
+    fn write_batch_with_sync(
+        &self,
+        prefix_name: &str,
+        batch: WriteBatch,
+        sync: bool,
+    ) -> Result<()> {
+        let method = if sync {
+            "write_batch_sync"
+        } else {
+            "write_batch"
+        };
+        record_metrics("db", prefix_name, method, self.metrics.as_ref()).call(|| {
+            let mut db_batch = DBWriteBatch::default();
+            let cf_handle = self.get_cf_handle(prefix_name)?;
+            for (key, write_op) in &batch.rows {
+                match write_op {
+                    WriteOp::Value(value) => db_batch.put_cf(cf_handle, key, value),
+                    WriteOp::Deletion => db_batch.delete_cf(cf_handle, key),
+                    WriteOp::Merge(_) => bail!("Merge is not supported by db storage."),
+                };
+            }
+            self.db.write_opt(db_batch, &Self::write_options(sync))?;
+            Ok(())
+        })
+    }
 }
 
 pub enum ScanDirection {
@@ -350,18 +413,65 @@ impl InnerStore for DBStorage {
     }
 
     fn put(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        if let Some(metrics) = self.metrics.as_ref() {
-            metrics
-                .storage_item_bytes
-                .with_label_values(&[prefix_name])
-                .observe((key.len() + value.len()) as f64);
-        }
+        self.put_with_sync(prefix_name, key, value, false)
+    }
 
-        record_metrics("db", prefix_name, "put", self.metrics.as_ref()).call(|| {
+    /// A read-modify-write like `put_if_absent`, only atomic against other read-modify-write
+    /// calls of this storage.
+    fn put_returning(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        record_metrics("db", prefix_name, "put_returning", self.metrics.as_ref()).call(|| {
             let cf_handle = self.get_cf_handle(prefix_name)?;
+            let _guard = self.read_modify_write_lock.lock();
+            let prev = self.db.get_cf(cf_handle, key.as_slice())?;
             self.db
                 .put_cf_opt(cf_handle, &key, &value, &Self::default_write_options())?;
-            Ok(())
+            Ok(prev)
+        })
+    }
+
+    /// RocksDB has no put if absent, it is a read-modify-write in the column family,
+    /// only atomic against other `put_if_absent` calls of this storage, not against `put`.
+    /// A rocksdb transaction is required to make it atomic against all the writes.
+    fn put_if_absent(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        record_metrics("db", prefix_name, "put_if_absent", self.metrics.as_ref()).call(|| {
+            let cf_handle = self.get_cf_handle(prefix_name)?;
+            let _guard = self.read_modify_write_lock.lock();
+            if self.db.get_cf(cf_handle, key.as_slice())?.is_some() {
+                return Ok(false);
+            }
+            self.db
+                .put_cf_opt(cf_handle, &key, &value, &Self::default_write_options())?;
+            Ok(true)
+        })
+    }
+
+    /// Same as `put_if_absent`, only atomic against other read-modify-write calls of this storage.
+    fn compare_and_swap(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        record_metrics("db", prefix_name, "compare_and_swap", self.metrics.as_ref()).call(|| {
+            let cf_handle = self.get_cf_handle(prefix_name)?;
+            let _guard = self.read_modify_write_lock.lock();
+            if self.db.get_cf(cf_handle, key.as_slice())? != expected {
+                return Ok(false);
+            }
+            match new {
+                Some(value) => {
+                    self.db
+                        .put_cf_opt(cf_handle, &key, &value, &Self::default_write_options())?
+                }
+                None => self.db.delete_cf(cf_handle, &key)?,
+            }
+            Ok(true)
         })
     }
 
@@ -381,20 +491,85 @@ impl InnerStore for DBStorage {
         })
     }
 
+    fn remove_returning(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        record_metrics("db", prefix_name, "remove_returning", self.metrics.as_ref()).call(|| {
+            let cf_handle = self.get_cf_handle(prefix_name)?;
+            let _guard = self.read_modify_write_lock.lock();
+            let prev = self.db.get_cf(cf_handle, key.as_slice())?;
+            if prev.is_some() {
+                self.db.delete_cf(cf_handle, &key)?;
+            }
+            Ok(prev)
+        })
+    }
+
     /// Writes a group of records wrapped in a WriteBatch.
     fn write_batch(&self, prefix_name: &str, batch: WriteBatch) -> Result<()> {
-        record_metrics("db", prefix_name, "write_batch", self.metrics.as_ref()).call(|| {
-            let mut db_batch = DBWriteBatch::default();
+        self.write_batch_with_sync(prefix_name, batch, false)
+    }
+
+    /// Write the batches of all the prefixes in one rocksdb write batch.
+    fn write_batch_multi(&self, batches: Vec<(&str, WriteBatch)>) -> Result<()> {
+        let mut db_batch = DBWriteBatch::default();
+        for (prefix_name, batch) in &batches {
             let cf_handle = self.get_cf_handle(prefix_name)?;
             for (key, write_op) in &batch.rows {
                 match write_op {
                     WriteOp::Value(value) => db_batch.put_cf(cf_handle, key, value),
                     WriteOp::Deletion => db_batch.delete_cf(cf_handle, key),
+                    WriteOp::Merge(_) => bail!("Merge is not supported by db storage."),
                 };
             }
-            self.db
-                .write_opt(db_batch, &Self::default_write_options())?;
-            Ok(())
+        }
+        self.db
+            .write_opt(db_batch, &Self::default_write_options())?;
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix_name: &str, start: Vec<u8>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        record_metrics("db", prefix_name, "scan_prefix", self.metrics.as_ref()).call(|| {
+            let cf_handle = self.get_cf_handle(prefix_name)?;
+            let mut db_iter = self
+                .db
+                .raw_iterator_cf_opt(cf_handle, ReadOptions::default());
+            db_iter.seek(&start);
+            let mut entries = vec![];
+            while db_iter.valid() {
+                let key = db_iter.key().expect("Iterator must be valid.");
+                let value = db_iter.value().expect("Iterator must be valid.");
+                entries.push((key.to_vec(), value.to_vec()));
+                db_iter.next();
+            }
+            db_iter.status()?;
+            Ok(entries)
+        })
+    }
+
+    /// The pairs are ordered by key, the scan seeks to `key_prefix` and stops at the first
+    /// key not starting with it.
+    fn scan_key_prefix(
+        &self,
+        prefix_name: &str,
+        key_prefix: Vec<u8>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        record_metrics("db", prefix_name, "scan_key_prefix", self.metrics.as_ref()).call(|| {
+            let cf_handle = self.get_cf_handle(prefix_name)?;
+            let mut db_iter = self
+                .db
+                .raw_iterator_cf_opt(cf_handle, ReadOptions::default());
+            db_iter.seek(&key_prefix);
+            let mut entries = vec![];
+            while db_iter.valid() {
+                let key = db_iter.key().expect("Iterator must be valid.");
+                if !key.starts_with(&key_prefix) {
+                    break;
+                }
+                let value = db_iter.value().expect("Iterator must be valid.");
+                entries.push((key.to_vec(), value.to_vec()));
+                db_iter.next();
+            }
+            db_iter.status()?;
+            Ok(entries)
         })
     }
 
@@ -407,33 +582,10 @@ impl InnerStore for DBStorage {
     }
 
     fn put_sync(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        if let Some(metrics) = self.metrics.as_ref() {
-            metrics
-                .storage_item_bytes
-                .with_label_values(&[prefix_name])
-                .observe((key.len() + value.len()) as f64);
-        }
-
-        record_metrics("db", prefix_name, "put_sync", self.metrics.as_ref()).call(|| {
-            let cf_handle = self.get_cf_handle(prefix_name)?;
-            self.db
-                .put_cf_opt(cf_handle, &key, &value, &Self::sync_write_options())?;
-            Ok(())
-        })
+        self.put_with_sync(prefix_name, key, value, true)
     }
 
     fn write_batch_sync(&self, prefix_name: &str, batch: WriteBatch) -> Result<()> {
-        record_metrics("db", prefix_name, "write_batch_sync", self.metrics.as_ref()).call(|| {
-            let mut db_batch = DBWriteBatch::default();
-            let cf_handle = self.get_cf_handle(prefix_name)?;
-            for (key, write_op) in &batch.rows {
-                match write_op {
-                    WriteOp::Value(value) => db_batch.put_cf(cf_handle, key, value),
-                    WriteOp::Deletion => db_batch.delete_cf(cf_handle, key),
-                };
-            }
-            self.db.write_opt(db_batch, &Self::sync_write_options())?;
-            Ok(())
-        })
+        self.write_batch_with_sync(prefix_name, batch, true)
     }
 }
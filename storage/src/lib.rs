@@ -32,6 +32,8 @@ use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
 
 pub mod accumulator;
+#[cfg(feature = "async")]
+pub mod async_storage;
 pub mod batch;
 pub mod block;
 pub mod block_info;
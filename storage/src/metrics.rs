@@ -1,6 +1,7 @@
 // Copyright (c) The Starcoin Core Contributors
 // SPDX-License-Identifier: Apache-2
 
+use crate::cache_storage::CacheLookup;
 use anyhow::Result;
 use starcoin_metrics::{
     self, register, HistogramOpts, HistogramVec, Opts, PrometheusError, Registry, UIntCounterVec,
@@ -8,13 +9,25 @@ use starcoin_metrics::{
 };
 // use std::time::Instant;
 use coarsetime::Instant;
+use std::cell::Cell;
+
+/// The hot methods timed by [`record_sampled_metrics`] are timed once every `METRICS_SAMPLE_RATE` calls.
+pub const METRICS_SAMPLE_RATE: u64 = 64;
+
+thread_local! {
+    static SAMPLE_COUNTER: Cell<u64> = Cell::new(0);
+}
 
 #[derive(Clone)]
 pub struct StorageMetrics {
     pub storage_rw_total: UIntCounterVec,
     pub storage_item_bytes: HistogramVec,
     pub storage_time: HistogramVec,
+    pub storage_sampled_time: HistogramVec,
     pub cache_items: UIntGauge,
+    pub cache_bytes: UIntGauge,
+    pub cache_hit_total: UIntCounterVec,
+    pub cache_miss_total: UIntCounterVec,
 }
 
 impl StorageMetrics {
@@ -48,18 +61,67 @@ impl StorageMetrics {
             registry,
         )?;
 
+        let storage_sampled_time = register(
+            HistogramVec::new(
+                HistogramOpts::new(
+                    "storage_sampled_time",
+                    "Histogram of storage, measure the time usage of sampled storage method calls.",
+                ),
+                &["storage_type", "key_type", "method"],
+            )?,
+            registry,
+        )?;
+
         let cache_items = register(
             UIntGauge::with_opts(Opts::new("cache_items", "How many items in cache"))?,
             registry,
         )?;
 
+        let cache_bytes = register(
+            UIntGauge::with_opts(Opts::new(
+                "cache_bytes",
+                "Approximate memory used by the items in cache",
+            ))?,
+            registry,
+        )?;
+
+        let cache_hit_total = register(
+            UIntCounterVec::new(
+                Opts::new("cache_hit_total", "Counters of how many cache hit"),
+                &["prefix_name", "method"],
+            )?,
+            registry,
+        )?;
+
+        let cache_miss_total = register(
+            UIntCounterVec::new(
+                Opts::new("cache_miss_total", "Counters of how many cache miss"),
+                &["prefix_name", "method"],
+            )?,
+            registry,
+        )?;
+
         Ok(Self {
             storage_rw_total,
             storage_item_bytes,
             storage_time,
+            storage_sampled_time,
             cache_items,
+            cache_bytes,
+            cache_hit_total,
+            cache_miss_total,
         })
     }
+
+    /// Record a cache lookup of `method` under `prefix_name` is hit or miss.
+    pub fn record_cache_lookup(&self, prefix_name: &str, method: &str, hit: bool) {
+        let counter = if hit {
+            &self.cache_hit_total
+        } else {
+            &self.cache_miss_total
+        };
+        counter.with_label_values(&[prefix_name, method]).inc();
+    }
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -103,6 +165,15 @@ impl AsResultType for Result<bool> {
     }
 }
 
+impl<T> AsResultType for Result<Vec<T>> {
+    fn as_result_type(&self) -> ResultType {
+        match self {
+            Ok(_) => ResultType::OK,
+            Err(_) => ResultType::ERROR,
+        }
+    }
+}
+
 impl<T> AsResultType for Result<Option<T>> {
     fn as_result_type(&self) -> ResultType {
         match self {
@@ -115,6 +186,16 @@ impl<T> AsResultType for Result<Option<T>> {
     }
 }
 
+impl AsResultType for Result<CacheLookup> {
+    fn as_result_type(&self) -> ResultType {
+        match self {
+            Ok(CacheLookup::Value(_)) => ResultType::SOME,
+            Ok(CacheLookup::Absent) | Ok(CacheLookup::Miss) => ResultType::NONE,
+            Err(_) => ResultType::ERROR,
+        }
+    }
+}
+
 pub struct MetricsRecord<'a> {
     storage_type: &'a str,
     key_type: &'a str,
@@ -182,3 +263,54 @@ pub fn record_metrics<'a>(
 ) -> MetricsRecord<'a> {
     MetricsRecord::new(storage_type, key_type, method, metrics)
 }
+
+/// Time the calls too hot to be timed every time, such as the cache `put` and `remove`.
+/// Only one of every [`METRICS_SAMPLE_RATE`] calls on a thread reads the clock and is observed
+/// in `storage_sampled_time`, the others only pay a thread local counter increment.
+/// So the histogram gives the latency distribution, but its count is about 1/[`METRICS_SAMPLE_RATE`]
+/// of the calls, and the calls are not counted in `storage_rw_total`.
+pub fn record_sampled_metrics<'a>(
+    storage_type: &'a str,
+    key_type: &'a str,
+    method: &'a str,
+    metrics: Option<&'a StorageMetrics>,
+) -> SampledMetricsRecord<'a> {
+    let metrics = metrics.filter(|_| {
+        SAMPLE_COUNTER.with(|counter| {
+            let count = counter.get().wrapping_add(1);
+            counter.set(count);
+            count % METRICS_SAMPLE_RATE == 0
+        })
+    });
+    SampledMetricsRecord {
+        storage_type,
+        key_type,
+        method,
+        timer: metrics.map(|_| Instant::now()),
+        metrics,
+    }
+}
+
+pub struct SampledMetricsRecord<'a> {
+    storage_type: &'a str,
+    key_type: &'a str,
+    method: &'a str,
+    timer: Option<Instant>,
+    metrics: Option<&'a StorageMetrics>,
+}
+
+impl<'a> SampledMetricsRecord<'a> {
+    pub fn call<R, F>(self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let r = f();
+        if let (Some(metrics), Some(timer)) = (self.metrics, self.timer) {
+            metrics
+                .storage_sampled_time
+                .with_label_values(&[self.storage_type, self.key_type, self.method])
+                .observe(timer.elapsed().as_f64());
+        }
+        r
+    }
+}
@@ -1,13 +1,15 @@
 // Copyright (c) The Starcoin Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
-pub use crate::batch::WriteBatch;
-use crate::cache_storage::CacheStorage;
+pub use crate::batch::{WriteBatch, WriteBatchGroup};
+use crate::cache_storage::{CacheLookup, CacheStorage};
 use crate::db_storage::{DBStorage, SchemaIterator};
 use crate::upgrade::DBUpgrade;
 use anyhow::{bail, format_err, Result};
 use byteorder::{BigEndian, ReadBytesExt};
 use crypto::HashValue;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::convert::TryInto;
 use std::fmt::Debug;
 use std::marker::PhantomData;
@@ -35,16 +37,140 @@ pub trait KVStore: Send + Sync {
 
 pub trait InnerStore: Send + Sync {
     fn get(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+    /// Get the value as an `Arc`, the cache backend shares the value instead of copying it.
+    fn get_arc(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Arc<Vec<u8>>>> {
+        Ok(self.get(prefix_name, key)?.map(Arc::new))
+    }
+    /// Get the value without affecting the eviction order of the cache, the default is `get`.
+    fn peek(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.get(prefix_name, key)
+    }
+    /// Get the values of `keys`, the result is in the same order as `keys`.
+    fn multi_get(&self, prefix_name: &str, keys: Vec<Vec<u8>>) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.into_iter()
+            .map(|key| self.get(prefix_name, key))
+            .collect()
+    }
     fn put(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+    /// Put the value and return the previous value of the key, the default gets then puts,
+    /// so it is not atomic against other writes.
+    fn put_returning(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        let prev = self.get(prefix_name, key.clone())?;
+        self.put(prefix_name, key, value)?;
+        Ok(prev)
+    }
+    /// Put the value only if the key is absent, return whether the value is inserted.
+    /// The check and the put are atomic against other `put_if_absent` calls.
+    fn put_if_absent(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<bool>;
+    /// Write `new` only if the current value equals `expected`, return whether the swap happened.
+    /// A missing key is treated as `None`, a `None` for `new` removes the key.
+    fn compare_and_swap(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool>;
     fn contains_key(&self, prefix_name: &str, key: Vec<u8>) -> Result<bool>;
+    /// Check the existence of `keys`, the result is in the same order as `keys`.
+    fn multi_contains(&self, prefix_name: &str, keys: Vec<Vec<u8>>) -> Result<Vec<bool>> {
+        keys.into_iter()
+            .map(|key| self.contains_key(prefix_name, key))
+            .collect()
+    }
     fn remove(&self, prefix_name: &str, key: Vec<u8>) -> Result<()>;
+    /// Remove the key and return its previous value.
+    fn remove_returning(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
     fn write_batch(&self, prefix_name: &str, batch: WriteBatch) -> Result<()>;
+    /// Write the batches of multiple prefixes in one call, the default writes them one by one.
+    fn write_batch_multi(&self, batches: Vec<(&str, WriteBatch)>) -> Result<()> {
+        for (prefix_name, batch) in batches {
+            self.write_batch(prefix_name, batch)?;
+        }
+        Ok(())
+    }
+    /// Write all the batches of the `group`, the RocksDB backed store writes them atomically.
+    fn write_batch_group(&self, group: WriteBatchGroup) -> Result<()> {
+        self.write_batch_multi(group.batches)
+    }
+    /// The key value pairs under `prefix_name` whose key is greater than or equal to `start`,
+    /// ordered by key.
+    fn scan_prefix(&self, prefix_name: &str, start: Vec<u8>) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+    /// The key value pairs under `prefix_name` whose key starts with `key_prefix`, e.g. to
+    /// enumerate the state. Only the RocksDB backend returns the pairs ordered by key, the
+    /// `CacheStorage` is hash-sharded and returns them in no particular order, so the callers
+    /// needing the order must sort them or scan the db directly.
+    /// The default filters `keys()` and gets every matched key, for the stores whose `keys()`
+    /// are the keys passed to `put`.
+    fn scan_key_prefix(
+        &self,
+        prefix_name: &str,
+        key_prefix: Vec<u8>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = vec![];
+        for key in self.keys()? {
+            if key.starts_with(&key_prefix) {
+                if let Some(value) = self.get(prefix_name, key.clone())? {
+                    entries.push((key, value));
+                }
+            }
+        }
+        Ok(entries)
+    }
     fn get_len(&self) -> Result<u64>;
     fn keys(&self) -> Result<Vec<Vec<u8>>>;
+    /// Same as `put`, but on a durable backend the write is persisted (the WAL is fsynced)
+    /// before returning, so it survives a crash of the machine. A pure in-memory store has
+    /// nothing to flush and may implement it as `put`, a store wrapping a durable backend
+    /// must forward the sync to that backend.
     fn put_sync(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+    /// The batch version of `put_sync`, with the same durability contract.
     fn write_batch_sync(&self, prefix_name: &str, batch: WriteBatch) -> Result<()>;
 }
 
+/// Get the value of `key` under `prefix_name` and decode it by bcs,
+/// the decode error tells the prefix and the key in hex.
+pub fn get_typed<T: DeserializeOwned>(
+    store: &dyn InnerStore,
+    prefix_name: &str,
+    key: Vec<u8>,
+) -> Result<Option<T>> {
+    match store.get(prefix_name, key.clone())? {
+        Some(value) => bcs_ext::from_bytes(&value).map(Some).map_err(|e| {
+            format_err!(
+                "decode value of key 0x{} in {} failed: {}",
+                hex::encode(&key),
+                prefix_name,
+                e
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Encode `value` by bcs and put it to `key` under `prefix_name`, the pair of [`get_typed`].
+pub fn put_typed<T: Serialize>(
+    store: &dyn InnerStore,
+    prefix_name: &str,
+    key: Vec<u8>,
+    value: &T,
+) -> Result<()> {
+    let value = bcs_ext::to_bytes(value).map_err(|e| {
+        format_err!(
+            "encode value of key 0x{} in {} failed: {}",
+            hex::encode(&key),
+            prefix_name,
+            e
+        )
+    })?;
+    store.put(prefix_name, key, value)
+}
+
 ///Storage instance type define
 #[derive(Clone)]
 #[allow(clippy::upper_case_acronyms)]
@@ -109,6 +235,53 @@ impl StorageInstance {
     pub fn check_upgrade(&mut self) -> Result<()> {
         DBUpgrade::check_upgrade(self)
     }
+
+    /// The `sync` is forwarded to the db, the cache is written after the db succeeds and has
+    /// nothing to flush.
+    fn put_with_sync(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        sync: bool,
+    ) -> Result<()> {
+        let put_db = |db: &DBStorage, key, value| {
+            if sync {
+                db.put_sync(prefix_name, key, value)
+            } else {
+                db.put(prefix_name, key, value)
+            }
+        };
+        match self {
+            StorageInstance::CACHE { cache } => cache.put(prefix_name, key, value),
+            StorageInstance::DB { db } => put_db(db, key, value),
+            StorageInstance::CacheAndDb { cache, db } => put_db(db, key.clone(), value.clone())
+                .and_then(|_| cache.put(prefix_name, key, value)),
+        }
+    }
+
+    fn write_batch_with_sync(
+        &self,
+        prefix_name: &str,
+        batch: WriteBatch,
+        sync: bool,
+    ) -> Result<()> {
+        let write_db = |db: &DBStorage, batch| {
+            if sync {
+                db.write_batch_sync(prefix_name, batch)
+            } else {
+                db.write_batch(prefix_name, batch)
+            }
+        };
+        match self {
+            StorageInstance::CACHE { cache } => cache.write_batch(prefix_name, batch),
+            StorageInstance::DB { db } => write_db(db, batch),
+            StorageInstance::CacheAndDb { cache, db } => match write_db(db, batch.clone()) {
+                Ok(_) => cache.write_batch(prefix_name, batch),
+                Err(err) => bail!("write batch db error: {}", err),
+            },
+        }
+    }
 }
 
 impl InnerStore for StorageInstance {
@@ -119,18 +292,23 @@ impl InnerStore for StorageInstance {
             StorageInstance::CacheAndDb { cache, db } => {
                 // first get from cache
                 // if from cache get non-existent, query from db
-                if let Ok(Some(value)) = cache.get(prefix_name, key.clone()) {
-                    Ok(Some(value))
-                } else {
-                    match db.get(prefix_name, key)? {
-                        Some(value) => {
-                            // cache.put_obj(prefix_name, key, CacheObject::Value(value.clone()))?;
-                            Ok(Some(value))
-                        }
-                        None => {
-                            // put null vec to cache for avoid repeatedly querying non-existent data from db
-                            // cache.put_obj(prefix_name, key, CACHE_NONE_OBJECT.clone())?;
-                            Ok(None)
+                match cache.lookup(prefix_name, key.clone()) {
+                    Ok(CacheLookup::Value(value)) => Ok(Some(value)),
+                    Ok(CacheLookup::Absent) => Ok(None),
+                    _ => {
+                        // read before the db, so a racing put is not shadowed by the miss.
+                        let epoch = cache.write_epoch(prefix_name, &key);
+                        match db.get(prefix_name, key.clone())? {
+                            Some(value) => {
+                                // cache.put_obj(prefix_name, key, CacheObject::Value(value.clone()))?;
+                                Ok(Some(value))
+                            }
+                            None => {
+                                // record the miss for avoid repeatedly querying non-existent data from db,
+                                // only if the negative cache is enabled.
+                                cache.put_absent(prefix_name, key, epoch)?;
+                                Ok(None)
+                            }
                         }
                     }
                 }
@@ -138,13 +316,77 @@ impl InnerStore for StorageInstance {
         }
     }
 
+    fn peek(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        match self {
+            StorageInstance::CACHE { cache } => cache.peek(prefix_name, key),
+            StorageInstance::DB { db } => db.peek(prefix_name, key),
+            StorageInstance::CacheAndDb { cache, db } => {
+                match cache.peek(prefix_name, key.clone())? {
+                    Some(value) => Ok(Some(value)),
+                    None => db.peek(prefix_name, key),
+                }
+            }
+        }
+    }
+
+    fn multi_get(&self, prefix_name: &str, keys: Vec<Vec<u8>>) -> Result<Vec<Option<Vec<u8>>>> {
+        match self {
+            StorageInstance::CACHE { cache } => cache.multi_get(prefix_name, keys),
+            StorageInstance::DB { db } => db.multi_get(prefix_name, keys),
+            StorageInstance::CacheAndDb { cache, db } => {
+                // first get from cache, then query the missed keys from db
+                let mut values = cache.multi_get(prefix_name, keys.clone())?;
+                let (missed_positions, missed_keys): (Vec<usize>, Vec<Vec<u8>>) = values
+                    .iter()
+                    .zip(keys)
+                    .enumerate()
+                    .filter(|(_, (value, _))| value.is_none())
+                    .map(|(pos, (_, key))| (pos, key))
+                    .unzip();
+                if !missed_keys.is_empty() {
+                    let db_values = db.multi_get(prefix_name, missed_keys)?;
+                    for (pos, value) in missed_positions.into_iter().zip(db_values) {
+                        values[pos] = value;
+                    }
+                }
+                Ok(values)
+            }
+        }
+    }
+
+    fn get_arc(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Arc<Vec<u8>>>> {
+        match self {
+            StorageInstance::CACHE { cache } => cache.get_arc(prefix_name, key),
+            StorageInstance::DB { db } => db.get_arc(prefix_name, key),
+            StorageInstance::CacheAndDb { cache, db } => {
+                if let Ok(Some(value)) = cache.get_arc(prefix_name, key.clone()) {
+                    Ok(Some(value))
+                } else {
+                    db.get_arc(prefix_name, key)
+                }
+            }
+        }
+    }
+
     fn put(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.put_with_sync(prefix_name, key, value, false)
+    }
+
+    fn put_returning(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
         match self {
-            StorageInstance::CACHE { cache } => cache.put(prefix_name, key, value),
-            StorageInstance::DB { db } => db.put(prefix_name, key, value),
-            StorageInstance::CacheAndDb { cache, db } => db
-                .put(prefix_name, key.clone(), value.clone())
-                .and_then(|_| cache.put(prefix_name, key, value)),
+            StorageInstance::CACHE { cache } => cache.put_returning(prefix_name, key, value),
+            StorageInstance::DB { db } => db.put_returning(prefix_name, key, value),
+            StorageInstance::CacheAndDb { cache, db } => {
+                // the cache only holds part of the entries, return the previous value of the db.
+                let prev = db.put_returning(prefix_name, key.clone(), value.clone())?;
+                cache.put(prefix_name, key, value)?;
+                Ok(prev)
+            }
         }
     }
 
@@ -161,6 +403,71 @@ impl InnerStore for StorageInstance {
         }
     }
 
+    fn multi_contains(&self, prefix_name: &str, keys: Vec<Vec<u8>>) -> Result<Vec<bool>> {
+        match self {
+            StorageInstance::CACHE { cache } => cache.multi_contains(prefix_name, keys),
+            StorageInstance::DB { db } => db.multi_contains(prefix_name, keys),
+            StorageInstance::CacheAndDb { cache, db } => {
+                // first check the cache, then check the missed keys in db
+                let mut exists = cache.multi_contains(prefix_name, keys.clone())?;
+                let (missed_positions, missed_keys): (Vec<usize>, Vec<Vec<u8>>) = exists
+                    .iter()
+                    .zip(keys)
+                    .enumerate()
+                    .filter(|(_, (exist, _))| !**exist)
+                    .map(|(pos, (_, key))| (pos, key))
+                    .unzip();
+                if !missed_keys.is_empty() {
+                    let db_exists = db.multi_contains(prefix_name, missed_keys)?;
+                    for (pos, exist) in missed_positions.into_iter().zip(db_exists) {
+                        exists[pos] = exist;
+                    }
+                }
+                Ok(exists)
+            }
+        }
+    }
+
+    fn put_if_absent(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        match self {
+            StorageInstance::CACHE { cache } => cache.put_if_absent(prefix_name, key, value),
+            StorageInstance::DB { db } => db.put_if_absent(prefix_name, key, value),
+            StorageInstance::CacheAndDb { cache, db } => {
+                let inserted = db.put_if_absent(prefix_name, key.clone(), value.clone())?;
+                if inserted {
+                    cache.put(prefix_name, key, value)?;
+                }
+                Ok(inserted)
+            }
+        }
+    }
+
+    fn compare_and_swap(
+        &self,
+        prefix_name: &str,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        match self {
+            StorageInstance::CACHE { cache } => {
+                cache.compare_and_swap(prefix_name, key, expected, new)
+            }
+            StorageInstance::DB { db } => db.compare_and_swap(prefix_name, key, expected, new),
+            StorageInstance::CacheAndDb { cache, db } => {
+                let swapped =
+                    db.compare_and_swap(prefix_name, key.clone(), expected, new.clone())?;
+                if swapped {
+                    match new {
+                        Some(value) => cache.put(prefix_name, key, value)?,
+                        None => cache.remove(prefix_name, key)?,
+                    }
+                }
+                Ok(swapped)
+            }
+        }
+    }
+
     fn remove(&self, prefix_name: &str, key: Vec<u8>) -> Result<()> {
         match self {
             StorageInstance::CACHE { cache } => cache.remove(prefix_name, key),
@@ -174,18 +481,58 @@ impl InnerStore for StorageInstance {
         }
     }
 
+    fn remove_returning(&self, prefix_name: &str, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        match self {
+            StorageInstance::CACHE { cache } => cache.remove_returning(prefix_name, key),
+            StorageInstance::DB { db } => db.remove_returning(prefix_name, key),
+            StorageInstance::CacheAndDb { cache, db } => {
+                let prev = db.remove_returning(prefix_name, key.clone())?;
+                cache.remove(prefix_name, key)?;
+                Ok(prev)
+            }
+        }
+    }
+
     fn write_batch(&self, prefix_name: &str, batch: WriteBatch) -> Result<()> {
+        self.write_batch_with_sync(prefix_name, batch, false)
+    }
+    fn write_batch_multi(&self, batches: Vec<(&str, WriteBatch)>) -> Result<()> {
         match self {
-            StorageInstance::CACHE { cache } => cache.write_batch(prefix_name, batch),
-            StorageInstance::DB { db } => db.write_batch(prefix_name, batch),
+            StorageInstance::CACHE { cache } => cache.write_batch_multi(batches),
+            StorageInstance::DB { db } => db.write_batch_multi(batches),
             StorageInstance::CacheAndDb { cache, db } => {
-                match db.write_batch(prefix_name, batch.clone()) {
-                    Ok(_) => cache.write_batch(prefix_name, batch),
+                match db.write_batch_multi(batches.clone()) {
+                    Ok(_) => cache.write_batch_multi(batches),
                     Err(err) => bail!("write batch db error: {}", err),
                 }
             }
         }
     }
+
+    fn scan_prefix(&self, prefix_name: &str, start: Vec<u8>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        match self {
+            StorageInstance::CACHE { cache } => cache.scan_prefix(prefix_name, start),
+            // the cache only holds part of the entries, scan the db.
+            StorageInstance::DB { db } | StorageInstance::CacheAndDb { cache: _, db } => {
+                db.scan_prefix(prefix_name, start)
+            }
+        }
+    }
+
+    fn scan_key_prefix(
+        &self,
+        prefix_name: &str,
+        key_prefix: Vec<u8>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        match self {
+            StorageInstance::CACHE { cache } => cache.scan_key_prefix(prefix_name, key_prefix),
+            // the cache only holds part of the entries, scan the db.
+            StorageInstance::DB { db } | StorageInstance::CacheAndDb { cache: _, db } => {
+                db.scan_key_prefix(prefix_name, key_prefix)
+            }
+        }
+    }
+
     fn get_len(&self) -> Result<u64> {
         match self {
             StorageInstance::CACHE { cache } => cache.get_len(),
@@ -203,26 +550,11 @@ impl InnerStore for StorageInstance {
     }
 
     fn put_sync(&self, prefix_name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        match self {
-            StorageInstance::CACHE { cache } => cache.put(prefix_name, key, value),
-            StorageInstance::DB { db } => db.put_sync(prefix_name, key, value),
-            StorageInstance::CacheAndDb { cache, db } => db
-                .put_sync(prefix_name, key.clone(), value.clone())
-                .and_then(|_| cache.put(prefix_name, key, value)),
-        }
+        self.put_with_sync(prefix_name, key, value, true)
     }
 
     fn write_batch_sync(&self, prefix_name: &str, batch: WriteBatch) -> Result<()> {
-        match self {
-            StorageInstance::CACHE { cache } => cache.write_batch(prefix_name, batch),
-            StorageInstance::DB { db } => db.write_batch_sync(prefix_name, batch),
-            StorageInstance::CacheAndDb { cache, db } => {
-                match db.write_batch_sync(prefix_name, batch.clone()) {
-                    Ok(_) => cache.write_batch(prefix_name, batch),
-                    Err(err) => bail!("write batch db error: {}", err),
-                }
-            }
-        }
+        self.write_batch_with_sync(prefix_name, batch, true)
     }
 }
 
@@ -268,6 +600,10 @@ where
         self.instance.get(self.prefix_name, key.to_vec())
     }
 
+    fn multiple_get(&self, keys: Vec<Vec<u8>>) -> Result<Vec<Option<Vec<u8>>>> {
+        self.instance.multi_get(self.prefix_name, keys)
+    }
+
     fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
         self.instance.put(self.prefix_name, key, value)
     }
@@ -323,8 +659,13 @@ pub trait ValueCodec: Clone + Sized + Debug + std::marker::Send + std::marker::S
 pub enum WriteOp<V> {
     Value(V),
     Deletion,
+    /// Merge the operand into the current value with the merge operator registered on the store.
+    Merge(V),
 }
 
+/// Compute the new value from the current value and the merge operand, see [`WriteOp::Merge`].
+pub type MergeOperator = Arc<dyn Fn(Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync>;
+
 impl<V> WriteOp<V>
 where
     V: ValueCodec,
@@ -333,6 +674,7 @@ where
         Ok(match self {
             WriteOp::Value(v) => WriteOp::Value(v.encode_value()?),
             WriteOp::Deletion => WriteOp::Deletion,
+            WriteOp::Merge(v) => WriteOp::Merge(v.encode_value()?),
         })
     }
 }
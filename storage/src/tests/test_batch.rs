@@ -4,7 +4,7 @@
 use crate::batch::WriteBatch;
 use crate::cache_storage::CacheStorage;
 use crate::db_storage::DBStorage;
-use crate::storage::{CodecWriteBatch, InnerStore, ValueCodec};
+use crate::storage::{CodecWriteBatch, InnerStore, ValueCodec, WriteOp};
 use crate::DEFAULT_PREFIX_NAME;
 use crypto::HashValue;
 use starcoin_config::RocksdbConfig;
@@ -163,3 +163,108 @@ fn test_batch_comm() {
     let result = db.write_batch(DEFAULT_PREFIX_NAME, new_batch2);
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_batch_dedup() {
+    let mut write_batch = WriteBatch::new();
+    write_batch.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+    write_batch.put(b"b".to_vec(), b"1".to_vec()).unwrap();
+    write_batch.delete(b"a".to_vec()).unwrap();
+    write_batch.put(b"c".to_vec(), b"1".to_vec()).unwrap();
+    write_batch.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+    write_batch.delete(b"c".to_vec()).unwrap();
+    write_batch.put(b"c".to_vec(), b"3".to_vec()).unwrap();
+    let mut deduped = write_batch.clone();
+    deduped.dedup();
+    assert_eq!(deduped.rows.len(), 3);
+    let keys: Vec<&[u8]> = deduped.rows.iter().map(|(key, _)| key.as_slice()).collect();
+    assert_eq!(keys, vec![b"a".as_ref(), b"b".as_ref(), b"c".as_ref()]);
+
+    let cache = CacheStorage::new(None);
+    cache.write_batch(DEFAULT_PREFIX_NAME, write_batch).unwrap();
+    assert_eq!(cache.get(DEFAULT_PREFIX_NAME, b"a".to_vec()).unwrap(), None);
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"b".to_vec()).unwrap(),
+        Some(b"2".to_vec())
+    );
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"c".to_vec()).unwrap(),
+        Some(b"3".to_vec())
+    );
+}
+
+#[test]
+fn test_batch_with_limit() {
+    let mut write_batch = WriteBatch::with_limit(1000);
+    for i in 0..1000u64 {
+        write_batch
+            .push_checked(i.to_be_bytes().to_vec(), WriteOp::Value(vec![0u8]))
+            .unwrap();
+    }
+    assert!(write_batch.put(b"k".to_vec(), vec![0u8]).is_err());
+    assert!(write_batch.delete(b"k".to_vec()).is_err());
+    assert_eq!(write_batch.rows.len(), 1000);
+
+    let mut unbounded = WriteBatch::new();
+    for i in 0..1001u64 {
+        unbounded.put(i.to_be_bytes().to_vec(), vec![0u8]).unwrap();
+    }
+}
+
+#[test]
+fn test_batch_byte_size() {
+    let mut write_batch = WriteBatch::new();
+    assert!(write_batch.is_empty());
+    assert_eq!(write_batch.byte_size(), 0);
+    write_batch.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+    write_batch.delete(b"key".to_vec()).unwrap();
+    assert_eq!(write_batch.len(), 2);
+    assert!(!write_batch.is_empty());
+    assert_eq!(write_batch.byte_size(), 3 + 5 + 3);
+}
+
+#[test]
+fn test_batch_append_and_concat() {
+    let mut first = WriteBatch::new();
+    first.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+    first.put(b"b".to_vec(), b"1".to_vec()).unwrap();
+    let mut second = WriteBatch::new();
+    second.delete(b"a".to_vec()).unwrap();
+    second.put(b"c".to_vec(), b"2".to_vec()).unwrap();
+    let mut third = WriteBatch::new();
+    third.put(b"b".to_vec(), b"3".to_vec()).unwrap();
+    third.delete(b"c".to_vec()).unwrap();
+
+    let mut appended = first.clone();
+    appended.append(second.clone()).unwrap();
+    appended.append(third.clone()).unwrap();
+    let concated = WriteBatch::concat(vec![first.clone(), second, third]);
+    assert_eq!(concated.len(), 6);
+    let keys = |batch: &WriteBatch| -> Vec<Vec<u8>> {
+        batch.rows.iter().map(|(key, _)| key.clone()).collect()
+    };
+    assert_eq!(keys(&appended), keys(&concated));
+
+    let tmpdir = starcoin_config::temp_dir();
+    let db_storage = DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None).unwrap();
+    let cache = CacheStorage::new(None);
+    db_storage
+        .write_batch(DEFAULT_PREFIX_NAME, concated.clone())
+        .unwrap();
+    cache.write_batch(DEFAULT_PREFIX_NAME, concated).unwrap();
+    fn assert_effective_state<S: InnerStore>(store: &S) {
+        assert_eq!(store.get(DEFAULT_PREFIX_NAME, b"a".to_vec()).unwrap(), None);
+        assert_eq!(
+            store.get(DEFAULT_PREFIX_NAME, b"b".to_vec()).unwrap(),
+            Some(b"3".to_vec())
+        );
+        assert_eq!(store.get(DEFAULT_PREFIX_NAME, b"c".to_vec()).unwrap(), None);
+    }
+    assert_effective_state(&db_storage);
+    assert_effective_state(&cache);
+
+    let mut limited = WriteBatch::with_limit(2);
+    limited.append(first.clone()).unwrap();
+    assert!(limited.append(first).is_err());
+    assert_eq!(limited.len(), 2);
+}
@@ -0,0 +1,1591 @@
+// Copyright (c) The Starcoin Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::batch::{WriteBatch, WriteBatchGroup};
+use crate::cache_storage::{
+    CacheLookup, CacheStorage, ComposedKey, EvictionPolicy, ShardLruCache, DEFAULT_SHARD_BITS,
+    ENTRY_OVERHEAD, MAX_SHARD_BITS,
+};
+use crate::metrics::{StorageMetrics, METRICS_SAMPLE_RATE};
+use crate::storage::InnerStore;
+use crate::{BLOCK_HEADER_PREFIX_NAME, BLOCK_PREFIX_NAME, DEFAULT_PREFIX_NAME};
+use crypto::HashValue;
+use starcoin_metrics::Registry;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn test_shard_lru_cache_with_shards() {
+    let cache = ShardLruCache::with_shards(1024, 6).unwrap();
+    assert_eq!(cache.num_shards(), 64);
+    for i in 0..100u64 {
+        cache.put(i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec());
+    }
+    assert_eq!(cache.len(), 100);
+    assert_eq!(
+        cache.get(&7u64.to_be_bytes()),
+        Some(7u64.to_le_bytes().to_vec())
+    );
+
+    let single = ShardLruCache::with_shards(16, 0).unwrap();
+    assert_eq!(single.num_shards(), 1);
+    single.put(b"key".to_vec(), b"value".to_vec());
+    assert!(single.contains(b"key"));
+
+    assert!(ShardLruCache::with_shards(16, MAX_SHARD_BITS + 1).is_err());
+    assert!(CacheStorage::new_with_shards(16, MAX_SHARD_BITS + 1, None).is_err());
+}
+
+#[test]
+fn test_cache_storage_with_shards() {
+    let cache = CacheStorage::new_with_shards(1024, 2, None).unwrap();
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"key".to_vec()).unwrap(),
+        Some(b"value".to_vec())
+    );
+    cache.remove(DEFAULT_PREFIX_NAME, b"key".to_vec()).unwrap();
+    assert_eq!(cache.get_len().unwrap(), 0);
+}
+
+#[test]
+fn test_cache_storage_with_ttl() {
+    let cache = CacheStorage::new_with_ttl(1024, Duration::from_millis(100), None);
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+    assert!(cache
+        .contains_key(DEFAULT_PREFIX_NAME, b"key".to_vec())
+        .unwrap());
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!cache
+        .contains_key(DEFAULT_PREFIX_NAME, b"key".to_vec())
+        .unwrap());
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"key".to_vec()).unwrap(),
+        None
+    );
+    assert_eq!(cache.get_len().unwrap(), 0);
+}
+
+#[test]
+fn test_shard_lru_cache_byte_limit() {
+    // 16 shards, 64 bytes per shard.
+    let cache = ShardLruCache::new_with_byte_limit(1024);
+    for i in 0..1000u64 {
+        cache.put(i.to_be_bytes().to_vec(), vec![0u8; 24]);
+        assert!(cache.byte_len() <= 1024);
+    }
+    assert_eq!(cache.byte_len(), cache.len() * 32);
+
+    let big_key = b"big".to_vec();
+    cache.put(big_key.clone(), vec![1u8; 4096]);
+    assert_eq!(cache.get(&big_key), Some(vec![1u8; 4096]));
+    assert_eq!(cache.pop(&big_key).map(|value| value.len()), Some(4096));
+    assert!(cache.byte_len() <= 1024);
+}
+
+#[test]
+fn test_shard_lru_cache_segmented() {
+    assert!(ShardLruCache::new_segmented(128, 0.0).is_err());
+    assert!(ShardLruCache::new_segmented(128, 1.0).is_err());
+
+    // 16 shards, every shard holds 4 probationary and 4 protected entries.
+    let segmented = ShardLruCache::new_segmented(128, 0.5).unwrap();
+    let plain = ShardLruCache::new(128);
+    let hot_key = b"hot".to_vec();
+    for cache in [&segmented, &plain] {
+        cache.put(hot_key.clone(), vec![1u8; 8]);
+        assert_eq!(cache.get(&hot_key), Some(vec![1u8; 8]));
+        for i in 0..10000u64 {
+            cache.put(i.to_be_bytes().to_vec(), vec![0u8; 8]);
+        }
+        assert!(cache.len() <= 128);
+    }
+    assert_eq!(segmented.get(&hot_key), Some(vec![1u8; 8]));
+    assert_eq!(segmented.byte_len(), 11 + (segmented.len() - 1) * 16);
+    assert_eq!(plain.get(&hot_key), None);
+}
+
+#[test]
+fn test_shard_lru_cache_fifo_and_lfu() {
+    let fifo = ShardLruCache::with_policy(2, 0, None, EvictionPolicy::Fifo).unwrap();
+    fifo.put(b"a".to_vec(), b"1".to_vec());
+    fifo.put(b"b".to_vec(), b"2".to_vec());
+    // the hit does not save the earliest put entry.
+    assert_eq!(fifo.get(b"a"), Some(b"1".to_vec()));
+    fifo.put(b"c".to_vec(), b"3".to_vec());
+    assert_eq!(fifo.get(b"a"), None);
+    assert_eq!(fifo.get(b"b"), Some(b"2".to_vec()));
+
+    let lfu = ShardLruCache::with_policy(3, 0, None, EvictionPolicy::Lfu).unwrap();
+    for key in [b"a", b"b", b"c"] {
+        lfu.put(key.to_vec(), key.to_vec());
+    }
+    lfu.get(b"a");
+    lfu.get(b"a");
+    lfu.get(b"b");
+    assert_eq!(
+        lfu.entries_lru_order()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>(),
+        vec![b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]
+    );
+    // the least frequently used c is evicted, then d as the earliest of the same frequency.
+    lfu.put(b"d".to_vec(), b"d".to_vec());
+    assert_eq!(lfu.get(b"c"), None);
+    lfu.put(b"e".to_vec(), b"e".to_vec());
+    assert_eq!(lfu.get(b"d"), None);
+    assert_eq!(lfu.len(), 3);
+    assert_eq!(lfu.entry_count(), 3);
+    for key in [b"a", b"b", b"e"] {
+        assert_eq!(lfu.peek(key), Some(key.to_vec()));
+    }
+    assert_eq!(lfu.pop(b"a"), Some(b"a".to_vec()));
+    lfu.clear();
+    assert!(lfu.is_empty());
+}
+
+/// The hit ratio of a hot set read a few times in every round, then a scan of cold keys
+/// larger than the cache, a lru cache is flushed by every scan.
+#[test]
+fn test_shard_lru_cache_eviction_policy_hit_ratio() {
+    let hit_ratio = |policy: EvictionPolicy| {
+        let cache = ShardLruCache::with_policy(256, DEFAULT_SHARD_BITS, None, policy).unwrap();
+        let hot_keys: Vec<Vec<u8>> = (0..16u64)
+            .map(|i| [&b"hot-"[..], &i.to_be_bytes()].concat())
+            .collect();
+        let (mut hits, mut reads) = (0, 0);
+        for round in 0..50u64 {
+            for _ in 0..3 {
+                for key in &hot_keys {
+                    reads += 1;
+                    if cache.get(key).is_some() {
+                        hits += 1;
+                    } else {
+                        cache.put(key.clone(), vec![0u8; 8]);
+                    }
+                }
+            }
+            for i in 0..1000u64 {
+                let key = [round.to_be_bytes(), i.to_be_bytes()].concat();
+                if cache.get(&key).is_none() {
+                    cache.put(key, vec![0u8; 8]);
+                }
+            }
+        }
+        hits as f64 / reads as f64
+    };
+    let lru = hit_ratio(EvictionPolicy::Lru);
+    let fifo = hit_ratio(EvictionPolicy::Fifo);
+    let lfu = hit_ratio(EvictionPolicy::Lfu);
+    let segmented = hit_ratio(EvictionPolicy::Segmented {
+        protected_ratio: 0.5,
+    });
+    // the hot keys only hit after the first read of every round.
+    assert!(lru < 0.7, "lru hit ratio {}", lru);
+    assert!(fifo < 0.7, "fifo hit ratio {}", fifo);
+    // the hot keys survive the scans.
+    assert!(lfu > 0.95, "lfu hit ratio {}", lfu);
+    assert!(segmented > 0.95, "segmented hit ratio {}", segmented);
+}
+
+#[test]
+fn test_cache_storage_eviction_policy() {
+    assert!(CacheStorage::builder()
+        .eviction_policy(EvictionPolicy::Segmented {
+            protected_ratio: 1.0
+        })
+        .build()
+        .is_err());
+    let cache = CacheStorage::builder()
+        .capacity(2)
+        .shard_bits(0)
+        .eviction_policy(EvictionPolicy::Fifo)
+        .build()
+        .unwrap();
+    for key in [b"1", b"2"] {
+        cache
+            .put(DEFAULT_PREFIX_NAME, key.to_vec(), key.to_vec())
+            .unwrap();
+    }
+    assert!(cache
+        .get(DEFAULT_PREFIX_NAME, b"1".to_vec())
+        .unwrap()
+        .is_some());
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"3".to_vec(), b"3".to_vec())
+        .unwrap();
+    assert_eq!(cache.get(DEFAULT_PREFIX_NAME, b"1".to_vec()).unwrap(), None);
+    assert_eq!(cache.get_len().unwrap(), 2);
+}
+
+#[test]
+fn test_shard_lru_cache_segmented_peek() {
+    let cache = ShardLruCache::new_segmented(128, 0.5).unwrap();
+    let hot_key = b"hot".to_vec();
+    let sampled_key = b"sampled".to_vec();
+    cache.put(hot_key.clone(), vec![1u8; 8]);
+    cache.put(sampled_key.clone(), vec![2u8; 8]);
+    assert_eq!(cache.get(&hot_key), Some(vec![1u8; 8]));
+    // a peek is not a hit, the sampled entry stays probationary.
+    for _ in 0..3 {
+        assert_eq!(cache.peek(&sampled_key), Some(vec![2u8; 8]));
+    }
+    for i in 0..10000u64 {
+        cache.put(i.to_be_bytes().to_vec(), vec![0u8; 8]);
+    }
+    assert_eq!(cache.peek(&hot_key), Some(vec![1u8; 8]));
+    assert_eq!(cache.peek(&sampled_key), None);
+}
+
+#[test]
+fn test_shard_lru_cache_entry_count() {
+    let plain = ShardLruCache::new(64);
+    let segmented = ShardLruCache::new_segmented(64, 0.5).unwrap();
+    for cache in [&plain, &segmented] {
+        for i in 0..200u64 {
+            // the puts beyond the capacity evict entries implicitly.
+            cache.put(i.to_be_bytes().to_vec(), vec![0u8; 8]);
+            cache.get(&(i / 2).to_be_bytes());
+            assert_eq!(cache.entry_count(), cache.len());
+        }
+        // overwrite an existing key does not add an entry.
+        let key = 199u64.to_be_bytes().to_vec();
+        cache.put(key.clone(), vec![1u8; 8]);
+        assert_eq!(cache.entry_count(), cache.len());
+        assert!(cache.pop(&key).is_some());
+        // remove an absent key does not decrement.
+        assert!(cache.pop(&key).is_none());
+        assert_eq!(cache.entry_count(), cache.len());
+        cache.clear();
+        assert_eq!(cache.entry_count(), 0);
+    }
+}
+
+#[test]
+fn test_cache_storage_byte_limit() {
+    let cache = CacheStorage::new_with_byte_limit(1024 * 1024, None);
+    for i in 0..1024u64 {
+        cache
+            .put(
+                DEFAULT_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                vec![0u8; 4096],
+            )
+            .unwrap();
+    }
+    assert!(cache.byte_len() <= 1024 * 1024);
+    assert!(cache.get_len().unwrap() < 1024);
+}
+
+#[test]
+fn test_cache_storage_multi_get() {
+    let cache = CacheStorage::new(None);
+    for i in (0..100u64).step_by(2) {
+        cache
+            .put(
+                DEFAULT_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                i.to_le_bytes().to_vec(),
+            )
+            .unwrap();
+    }
+    let keys: Vec<Vec<u8>> = (0..100u64).map(|i| i.to_be_bytes().to_vec()).collect();
+    let values = cache.multi_get(DEFAULT_PREFIX_NAME, keys).unwrap();
+    assert_eq!(values.len(), 100);
+    for (i, value) in values.into_iter().enumerate() {
+        if i % 2 == 0 {
+            assert_eq!(value, Some((i as u64).to_le_bytes().to_vec()));
+        } else {
+            assert_eq!(value, None);
+        }
+    }
+}
+
+#[test]
+fn test_cache_storage_multi_contains() {
+    let cache = CacheStorage::new(None);
+    for i in (0..100u64).step_by(3) {
+        cache
+            .put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+    }
+    let keys: Vec<Vec<u8>> = (0..100u64)
+        .rev()
+        .map(|i| i.to_be_bytes().to_vec())
+        .collect();
+    let exists = cache.multi_contains(DEFAULT_PREFIX_NAME, keys).unwrap();
+    assert_eq!(
+        exists,
+        (0..100u64).rev().map(|i| i % 3 == 0).collect::<Vec<_>>()
+    );
+    assert!(cache
+        .multi_contains(BLOCK_PREFIX_NAME, vec![0u64.to_be_bytes().to_vec()])
+        .unwrap()
+        .iter()
+        .all(|exist| !exist));
+}
+
+#[test]
+fn test_shard_lru_cache_get_arc() {
+    let cache = ShardLruCache::new(16);
+    cache.put(b"key".to_vec(), vec![1u8; 1024]);
+    let first = cache.get_arc(b"key").unwrap();
+    let second = cache.get_arc(b"key").unwrap();
+    assert!(std::sync::Arc::ptr_eq(&first, &second));
+    assert_eq!(cache.get(b"key"), Some(vec![1u8; 1024]));
+    assert_eq!(cache.pop(b"key"), Some(vec![1u8; 1024]));
+    assert_eq!(first.as_slice(), &[1u8; 1024][..]);
+}
+
+#[test]
+fn test_cache_storage_remove_prefix() {
+    let cache = CacheStorage::new(None);
+    for i in 0..10u64 {
+        cache
+            .put(BLOCK_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+        cache
+            .put(
+                BLOCK_HEADER_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                vec![1u8],
+            )
+            .unwrap();
+    }
+    assert_eq!(cache.remove_prefix(BLOCK_PREFIX_NAME).unwrap(), 10);
+    assert_eq!(cache.get_len().unwrap(), 10);
+    assert_eq!(
+        cache
+            .get(BLOCK_HEADER_PREFIX_NAME, 1u64.to_be_bytes().to_vec())
+            .unwrap(),
+        Some(vec![1u8])
+    );
+}
+
+#[test]
+fn test_cache_storage_replace_prefix() {
+    let cache = CacheStorage::new(None);
+    for i in 0..10u64 {
+        cache
+            .put(BLOCK_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+        cache
+            .put(
+                BLOCK_HEADER_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                vec![1u8],
+            )
+            .unwrap();
+    }
+    // keep 5..10, drop 0..5 and add 10..15.
+    let entries = (5..15u64)
+        .map(|i| (i.to_be_bytes().to_vec(), vec![2u8]))
+        .collect();
+    cache.replace_prefix(BLOCK_PREFIX_NAME, entries).unwrap();
+    assert_eq!(cache.get_len().unwrap(), 20);
+    for i in 0..15u64 {
+        let expect = if i < 5 { None } else { Some(vec![2u8]) };
+        assert_eq!(
+            cache
+                .get(BLOCK_PREFIX_NAME, i.to_be_bytes().to_vec())
+                .unwrap(),
+            expect
+        );
+    }
+    assert_eq!(
+        cache
+            .keys_with_prefix(BLOCK_HEADER_PREFIX_NAME)
+            .unwrap()
+            .len(),
+        10
+    );
+}
+
+#[test]
+fn test_shard_lru_cache_replace_prefix_keeps_shared_keys() {
+    let cache = Arc::new(ShardLruCache::new(1024));
+    let shared_keys: Vec<Vec<u8>> = (0..32u64)
+        .map(|i| [&b"p-"[..], &i.to_be_bytes()].concat())
+        .collect();
+    cache.put_all(
+        shared_keys
+            .iter()
+            .map(|key| (key.clone(), vec![0u8]))
+            .collect(),
+    );
+    let reader = {
+        let cache = cache.clone();
+        let shared_keys = shared_keys.clone();
+        std::thread::spawn(move || {
+            for _ in 0..200 {
+                for key in &shared_keys {
+                    assert!(cache.contains(key));
+                }
+            }
+        })
+    };
+    for round in 0..200u64 {
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = shared_keys
+            .iter()
+            .map(|key| (key.clone(), vec![round as u8]))
+            .collect();
+        entries.push(([&b"p-round-"[..], &round.to_be_bytes()].concat(), vec![0u8]));
+        cache.replace_prefix(b"p-", entries);
+    }
+    reader.join().unwrap();
+    // only the shared keys and the entry of the last round are left.
+    assert_eq!(cache.len(), shared_keys.len() + 1);
+    assert_eq!(cache.get(&shared_keys[0]), Some(vec![199u8]));
+}
+
+#[test]
+fn test_shard_lru_cache_put_with_ttl() {
+    let cache = ShardLruCache::new(1024);
+    cache.put(b"forever".to_vec(), b"value".to_vec());
+    for i in 0..10u64 {
+        cache.put_with_ttl(
+            i.to_be_bytes().to_vec(),
+            b"value".to_vec(),
+            Duration::from_millis(100),
+        );
+    }
+    assert!(cache.contains(&1u64.to_be_bytes()));
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!cache.contains(&1u64.to_be_bytes()));
+    assert_eq!(cache.get(&2u64.to_be_bytes()), None);
+    assert_eq!(cache.len(), 9);
+    assert_eq!(cache.purge_expired(), 8);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.get(b"forever"), Some(b"value".to_vec()));
+}
+
+#[test]
+fn test_cache_storage_prefix_isolation() {
+    let cache = CacheStorage::new(None);
+    // with plain concatenation, both keys compose to `block_header1`.
+    cache
+        .put(BLOCK_PREFIX_NAME, b"_header1".to_vec(), b"block".to_vec())
+        .unwrap();
+    assert_eq!(
+        cache.get(BLOCK_HEADER_PREFIX_NAME, b"1".to_vec()).unwrap(),
+        None
+    );
+    cache
+        .put(BLOCK_HEADER_PREFIX_NAME, b"1".to_vec(), b"header".to_vec())
+        .unwrap();
+    assert_eq!(
+        cache.get(BLOCK_PREFIX_NAME, b"_header1".to_vec()).unwrap(),
+        Some(b"block".to_vec())
+    );
+    assert_eq!(
+        cache.get(BLOCK_HEADER_PREFIX_NAME, b"1".to_vec()).unwrap(),
+        Some(b"header".to_vec())
+    );
+    assert_eq!(cache.remove_prefix(BLOCK_PREFIX_NAME).unwrap(), 1);
+    assert_eq!(
+        cache.get(BLOCK_HEADER_PREFIX_NAME, b"1".to_vec()).unwrap(),
+        Some(b"header".to_vec())
+    );
+}
+
+#[test]
+fn test_shard_lru_cache_distribution() {
+    let cache = ShardLruCache::new(1024);
+    let mut shard_lens = vec![0usize; cache.num_shards()];
+    for i in 0..1600u64 {
+        shard_lens[cache.shard_index(&i.to_be_bytes())] += 1;
+    }
+    // 100 keys per shard on average.
+    for len in shard_lens {
+        assert!(len > 50 && len < 150, "unbalanced shard len {}", len);
+    }
+}
+
+/// The variance of the shard lens relative to the mean, for prefixed 32 bytes hash keys.
+fn shard_fill_variance<S: BuildHasher>(cache: &ShardLruCache<S>) -> f64 {
+    let mut shard_lens = vec![0f64; cache.num_shards()];
+    for _ in 0..cache.num_shards() * 1000 {
+        let mut key = BLOCK_PREFIX_NAME.as_bytes().to_vec();
+        key.extend(HashValue::random().to_vec());
+        shard_lens[cache.shard_index(&key)] += 1f64;
+    }
+    let mean = shard_lens.iter().sum::<f64>() / shard_lens.len() as f64;
+    let variance = shard_lens
+        .iter()
+        .map(|len| (len - mean).powi(2))
+        .sum::<f64>()
+        / shard_lens.len() as f64;
+    variance / (mean * mean)
+}
+
+#[test]
+fn test_shard_lru_cache_with_hasher() {
+    let fnv = ShardLruCache::with_shards(1024, 6).unwrap();
+    let sip = ShardLruCache::with_hasher(1024, 6, RandomState::new()).unwrap();
+    assert_eq!(sip.num_shards(), 64);
+    // 1000 keys per shard, the relative variance of a uniform distribution is about 0.001.
+    for variance in [shard_fill_variance(&fnv), shard_fill_variance(&sip)] {
+        assert!(variance < 0.01, "unbalanced shards, variance {}", variance);
+    }
+
+    sip.put(b"k".to_vec(), b"v".to_vec());
+    assert_eq!(sip.get(b"k"), Some(b"v".to_vec()));
+}
+
+#[test]
+fn test_cache_storage_keys_with_prefix() {
+    let cache = CacheStorage::new(None);
+    for i in 0..10u64 {
+        cache
+            .put(BLOCK_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+        cache
+            .put(
+                BLOCK_HEADER_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                vec![1u8],
+            )
+            .unwrap();
+    }
+    let mut keys = cache.keys_with_prefix(BLOCK_PREFIX_NAME).unwrap();
+    keys.sort();
+    let expect: Vec<Vec<u8>> = (0..10u64).map(|i| i.to_be_bytes().to_vec()).collect();
+    assert_eq!(keys, expect);
+    assert!(cache
+        .keys_with_prefix(DEFAULT_PREFIX_NAME)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_cache_storage_get_or_insert_with() {
+    let cache = CacheStorage::new(None);
+    let value = cache
+        .get_or_insert_with(DEFAULT_PREFIX_NAME, b"key".to_vec(), || b"first".to_vec())
+        .unwrap();
+    assert_eq!(value, b"first".to_vec());
+    let value = cache
+        .get_or_insert_with(DEFAULT_PREFIX_NAME, b"key".to_vec(), || {
+            panic!("should not compute value for existing key")
+        })
+        .unwrap();
+    assert_eq!(value, b"first".to_vec());
+    assert_eq!(cache.get_len().unwrap(), 1);
+}
+
+#[test]
+fn test_cache_storage_export_import() {
+    let cache = CacheStorage::new(None);
+    for i in 0..100u64 {
+        cache
+            .put(
+                DEFAULT_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                i.to_le_bytes().to_vec(),
+            )
+            .unwrap();
+    }
+    let entries = cache.export();
+    assert_eq!(entries.len(), 100);
+
+    let restored = CacheStorage::new(None);
+    restored.import(entries.clone());
+    assert_eq!(restored.get_len().unwrap(), 100);
+    assert_eq!(
+        restored
+            .get(DEFAULT_PREFIX_NAME, 42u64.to_be_bytes().to_vec())
+            .unwrap(),
+        Some(42u64.to_le_bytes().to_vec())
+    );
+
+    let small = CacheStorage::new_with_capacity(16, None);
+    small.import(entries);
+    assert!(small.get_len().unwrap() <= 16);
+}
+
+#[test]
+fn test_cache_storage_write_batch_multi() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    cache
+        .put(BLOCK_PREFIX_NAME, b"removed".to_vec(), b"v".to_vec())
+        .unwrap();
+
+    let mut block_batch = WriteBatch::new();
+    block_batch.put(b"k1".to_vec(), b"v1".to_vec()).unwrap();
+    block_batch.delete(b"removed".to_vec()).unwrap();
+    let mut header_batch = WriteBatch::new();
+    header_batch.put(b"k1".to_vec(), b"h1".to_vec()).unwrap();
+    header_batch.put(b"k2".to_vec(), b"h2".to_vec()).unwrap();
+    header_batch.put(b"k2".to_vec(), b"h3".to_vec()).unwrap();
+    cache
+        .write_batch_multi(vec![
+            (BLOCK_PREFIX_NAME, block_batch),
+            (BLOCK_HEADER_PREFIX_NAME, header_batch),
+        ])
+        .unwrap();
+
+    assert_eq!(
+        cache.get(BLOCK_PREFIX_NAME, b"k1".to_vec()).unwrap(),
+        Some(b"v1".to_vec())
+    );
+    assert_eq!(
+        cache.get(BLOCK_PREFIX_NAME, b"removed".to_vec()).unwrap(),
+        None
+    );
+    assert_eq!(
+        cache.get(BLOCK_HEADER_PREFIX_NAME, b"k1".to_vec()).unwrap(),
+        Some(b"h1".to_vec())
+    );
+    assert_eq!(
+        cache.get(BLOCK_HEADER_PREFIX_NAME, b"k2".to_vec()).unwrap(),
+        Some(b"h3".to_vec())
+    );
+    assert_eq!(cache.get_len().unwrap(), 3);
+}
+
+#[test]
+fn test_shard_lru_cache_put_if_absent() {
+    let cache = ShardLruCache::new_with_ttl(16, Duration::from_millis(50));
+    assert!(cache.put_if_absent(b"k".to_vec(), b"v1".to_vec()));
+    assert!(!cache.put_if_absent(b"k".to_vec(), b"v2".to_vec()));
+    assert_eq!(cache.get(b"k"), Some(b"v1".to_vec()));
+    // the expired entry is treated as absent.
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(cache.put_if_absent(b"k".to_vec(), b"v3".to_vec()));
+    assert_eq!(cache.get(b"k"), Some(b"v3".to_vec()));
+}
+
+#[test]
+fn test_cache_storage_snapshot() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    for i in 0..10u64 {
+        cache
+            .put(
+                DEFAULT_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                i.to_le_bytes().to_vec(),
+            )
+            .unwrap();
+    }
+    let snapshot = cache.snapshot();
+    // the snapshot is not changed by later writes.
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"new".to_vec(), b"v".to_vec())
+        .unwrap();
+    cache
+        .put(
+            DEFAULT_PREFIX_NAME,
+            0u64.to_be_bytes().to_vec(),
+            b"v".to_vec(),
+        )
+        .unwrap();
+    cache
+        .remove(DEFAULT_PREFIX_NAME, 1u64.to_be_bytes().to_vec())
+        .unwrap();
+    assert_eq!(snapshot.len(), 10);
+    assert!(!snapshot.contains(DEFAULT_PREFIX_NAME, b"new"));
+    assert!(!snapshot.contains(BLOCK_PREFIX_NAME, &0u64.to_be_bytes()));
+    assert_eq!(
+        snapshot.get(DEFAULT_PREFIX_NAME, &0u64.to_be_bytes()),
+        Some(0u64.to_le_bytes().as_ref())
+    );
+    assert!(snapshot.contains(DEFAULT_PREFIX_NAME, &1u64.to_be_bytes()));
+    let mut keys = snapshot.keys(DEFAULT_PREFIX_NAME);
+    keys.sort();
+    assert_eq!(
+        keys,
+        (0..10u64)
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect::<Vec<_>>()
+    );
+    assert!(snapshot.keys(BLOCK_PREFIX_NAME).is_empty());
+    let restored = CacheStorage::new_with_capacity(1024, None);
+    restored.import(snapshot.into_iter().collect());
+    for i in 0..10u64 {
+        assert_eq!(
+            restored
+                .get(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec())
+                .unwrap(),
+            Some(i.to_le_bytes().to_vec())
+        );
+    }
+}
+
+#[test]
+fn test_cache_storage_preload() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    let entries = (0..100u64)
+        .map(|i| (i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec()))
+        .collect::<Vec<_>>();
+    cache.preload(BLOCK_PREFIX_NAME, entries.clone()).unwrap();
+    assert_eq!(cache.get_len().unwrap(), 100);
+    for (key, value) in entries {
+        assert_eq!(
+            cache.get(BLOCK_PREFIX_NAME, key.clone()).unwrap(),
+            Some(value)
+        );
+        assert_eq!(cache.get(DEFAULT_PREFIX_NAME, key).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_shard_lru_cache_put_all() {
+    let cache = ShardLruCache::new(1024);
+    let mut entries = (0..100u64)
+        .map(|i| (i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec()))
+        .collect::<Vec<_>>();
+    // the later entries of the same keys override the earlier ones.
+    entries.extend((0..10u64).map(|i| (i.to_be_bytes().to_vec(), b"new".to_vec())));
+    cache.put_all(entries);
+    assert_eq!(cache.len(), 100);
+    for i in 0..100u64 {
+        let expect = if i < 10 {
+            b"new".to_vec()
+        } else {
+            i.to_le_bytes().to_vec()
+        };
+        assert_eq!(cache.get(&i.to_be_bytes()), Some(expect));
+    }
+
+    let storage = CacheStorage::new_with_capacity(1024, None);
+    storage.put_all(
+        BLOCK_PREFIX_NAME,
+        vec![
+            (b"k".to_vec(), b"v1".to_vec()),
+            (b"k".to_vec(), b"v2".to_vec()),
+        ],
+    );
+    assert_eq!(
+        storage.get(BLOCK_PREFIX_NAME, b"k".to_vec()).unwrap(),
+        Some(b"v2".to_vec())
+    );
+    assert_eq!(
+        storage.get(DEFAULT_PREFIX_NAME, b"k".to_vec()).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_cache_storage_compare_and_swap() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    let key = b"k".to_vec();
+    // insert only if absent.
+    assert!(cache
+        .compare_and_swap(DEFAULT_PREFIX_NAME, key.clone(), None, Some(b"v1".to_vec()))
+        .unwrap());
+    assert!(!cache
+        .compare_and_swap(DEFAULT_PREFIX_NAME, key.clone(), None, Some(b"v2".to_vec()))
+        .unwrap());
+    // swap on the matched value.
+    assert!(!cache
+        .compare_and_swap(
+            DEFAULT_PREFIX_NAME,
+            key.clone(),
+            Some(b"v2".to_vec()),
+            Some(b"v3".to_vec())
+        )
+        .unwrap());
+    assert!(cache
+        .compare_and_swap(
+            DEFAULT_PREFIX_NAME,
+            key.clone(),
+            Some(b"v1".to_vec()),
+            Some(b"v3".to_vec())
+        )
+        .unwrap());
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        Some(b"v3".to_vec())
+    );
+    // delete on the matched value.
+    assert!(cache
+        .compare_and_swap(DEFAULT_PREFIX_NAME, key.clone(), Some(b"v3".to_vec()), None)
+        .unwrap());
+    assert_eq!(cache.get(DEFAULT_PREFIX_NAME, key).unwrap(), None);
+}
+
+#[test]
+fn test_shard_lru_cache_memory_usage() {
+    let cache = ShardLruCache::new(64);
+    assert_eq!(cache.memory_usage(), 0);
+    // puts, overwrites, pops and evictions all update the running total.
+    for i in 0..200u64 {
+        cache.put(i.to_be_bytes().to_vec(), vec![0u8; (i % 7) as usize]);
+    }
+    for i in 150..180u64 {
+        cache.put(i.to_be_bytes().to_vec(), vec![1u8; 32]);
+    }
+    for i in 180..190u64 {
+        cache.pop(&i.to_be_bytes());
+    }
+    assert_eq!(cache.memory_usage(), cache.scan_memory_usage());
+    assert_eq!(
+        cache.memory_usage(),
+        (cache.byte_len() + cache.len() * ENTRY_OVERHEAD) as u64
+    );
+}
+
+#[test]
+fn test_cache_storage_effective_entry_size() {
+    assert_eq!(
+        CacheStorage::prefix_overhead(BLOCK_PREFIX_NAME),
+        BLOCK_PREFIX_NAME.len() + 1
+    );
+    let cache = CacheStorage::new(None);
+    for i in 0..10u64 {
+        cache
+            .put(BLOCK_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8; 32])
+            .unwrap();
+    }
+    assert_eq!(
+        cache.memory_usage(),
+        10 * CacheStorage::effective_entry_size(BLOCK_PREFIX_NAME, 8, 32) as u64
+    );
+}
+
+#[test]
+fn test_cache_storage_remove_returning() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"k".to_vec(), b"v".to_vec())
+        .unwrap();
+    assert_eq!(
+        cache
+            .remove_returning(DEFAULT_PREFIX_NAME, b"k".to_vec())
+            .unwrap(),
+        Some(b"v".to_vec())
+    );
+    assert_eq!(
+        cache
+            .remove_returning(DEFAULT_PREFIX_NAME, b"k".to_vec())
+            .unwrap(),
+        None
+    );
+    assert_eq!(cache.get_len().unwrap(), 0);
+}
+
+#[test]
+fn test_cache_storage_write_batch_group() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    let mut group = WriteBatchGroup::new();
+    let mut block_batch = WriteBatch::new();
+    block_batch.put(b"k".to_vec(), b"block".to_vec()).unwrap();
+    group.add(BLOCK_PREFIX_NAME, block_batch);
+    let mut header_batch = WriteBatch::new();
+    header_batch.put(b"k".to_vec(), b"header".to_vec()).unwrap();
+    group.add(BLOCK_HEADER_PREFIX_NAME, header_batch);
+    cache.write_batch_group(group).unwrap();
+
+    assert_eq!(
+        cache.get(BLOCK_PREFIX_NAME, b"k".to_vec()).unwrap(),
+        Some(b"block".to_vec())
+    );
+    assert_eq!(
+        cache.get(BLOCK_HEADER_PREFIX_NAME, b"k".to_vec()).unwrap(),
+        Some(b"header".to_vec())
+    );
+}
+
+#[test]
+fn test_cache_storage_write_batch_merge() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    cache.register_merge_operator(
+        DEFAULT_PREFIX_NAME,
+        Arc::new(|current: Option<&[u8]>, operand: &[u8]| {
+            let mut value = current.map(|v| v.to_vec()).unwrap_or_default();
+            value.extend_from_slice(operand);
+            value
+        }),
+    );
+    let mut batch = WriteBatch::new();
+    batch.merge(b"k".to_vec(), b"a".to_vec()).unwrap();
+    batch.merge(b"k".to_vec(), b"b".to_vec()).unwrap();
+    cache.write_batch(DEFAULT_PREFIX_NAME, batch).unwrap();
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"k".to_vec()).unwrap(),
+        Some(b"ab".to_vec())
+    );
+
+    // without a merge operator, the operand overwrites the value.
+    let mut batch = WriteBatch::new();
+    batch.put(b"k".to_vec(), b"a".to_vec()).unwrap();
+    batch.merge(b"k".to_vec(), b"b".to_vec()).unwrap();
+    cache.write_batch(BLOCK_PREFIX_NAME, batch).unwrap();
+    assert_eq!(
+        cache.get(BLOCK_PREFIX_NAME, b"k".to_vec()).unwrap(),
+        Some(b"b".to_vec())
+    );
+}
+
+#[test]
+fn test_cache_storage_get_if_present() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"k".to_vec(), b"v".to_vec())
+        .unwrap();
+    assert_eq!(
+        cache
+            .get_if_present(DEFAULT_PREFIX_NAME, b"k".to_vec())
+            .unwrap(),
+        Some(b"v".to_vec())
+    );
+    assert_eq!(
+        cache
+            .get_if_present(BLOCK_PREFIX_NAME, b"k".to_vec())
+            .unwrap(),
+        None
+    );
+
+    let key = ComposedKey::new(BLOCK_PREFIX_NAME, b"k".to_vec());
+    cache.put_composed(key.clone(), b"block".to_vec());
+    assert_eq!(cache.get_composed(&key), Some(b"block".to_vec()));
+    assert_eq!(
+        cache.get(BLOCK_PREFIX_NAME, b"k".to_vec()).unwrap(),
+        Some(b"block".to_vec())
+    );
+    assert_eq!(cache.remove_composed(&key), Some(b"block".to_vec()));
+    assert_eq!(cache.get_composed(&key), None);
+    assert_eq!(cache.get_len().unwrap(), 1);
+}
+
+#[test]
+fn test_cache_storage_on_evict() {
+    let evicted = Arc::new(Mutex::new(vec![]));
+    let evicted_keys = evicted.clone();
+    let cache = CacheStorage::new_with_shards(2, 0, None)
+        .unwrap()
+        .with_on_evict(Arc::new(move |key: &[u8], value: &[u8]| {
+            evicted_keys
+                .lock()
+                .unwrap()
+                .push((key.to_vec(), value.to_vec()))
+        }));
+    for key in [b"1", b"2", b"3"] {
+        cache
+            .put(DEFAULT_PREFIX_NAME, key.to_vec(), key.to_vec())
+            .unwrap();
+    }
+    // the explicit remove is not an eviction.
+    cache.remove(DEFAULT_PREFIX_NAME, b"3".to_vec()).unwrap();
+    assert_eq!(
+        evicted.lock().unwrap().as_slice(),
+        &[(
+            ComposedKey::new(DEFAULT_PREFIX_NAME, b"1".to_vec())
+                .as_bytes()
+                .to_vec(),
+            b"1".to_vec()
+        )]
+    );
+}
+
+#[test]
+fn test_cache_storage_negative_cache() {
+    let key = b"absent".to_vec();
+    // a cache without the negative cache records nothing.
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    let epoch = cache.write_epoch(DEFAULT_PREFIX_NAME, &key);
+    cache
+        .put_absent(DEFAULT_PREFIX_NAME, key.clone(), epoch)
+        .unwrap();
+    assert_eq!(
+        cache.lookup(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        CacheLookup::Miss
+    );
+
+    let cache = CacheStorage::new_with_capacity(1024, None).with_negative_cache(true);
+    let epoch = cache.write_epoch(DEFAULT_PREFIX_NAME, &key);
+    cache
+        .put_absent(DEFAULT_PREFIX_NAME, key.clone(), epoch)
+        .unwrap();
+    assert_eq!(
+        cache.lookup(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        CacheLookup::Absent
+    );
+    // the tombstone is bound to its prefix, and is invisible to the plain get.
+    assert_eq!(
+        cache.lookup(BLOCK_PREFIX_NAME, key.clone()).unwrap(),
+        CacheLookup::Miss
+    );
+    assert_eq!(cache.get(DEFAULT_PREFIX_NAME, key.clone()).unwrap(), None);
+    assert_eq!(cache.get_len().unwrap(), 0);
+
+    // a put overwrites the tombstone, and a stale miss does not shadow the value.
+    cache
+        .put(DEFAULT_PREFIX_NAME, key.clone(), b"value".to_vec())
+        .unwrap();
+    let epoch = cache.write_epoch(DEFAULT_PREFIX_NAME, &key);
+    cache
+        .put_absent(DEFAULT_PREFIX_NAME, key.clone(), epoch)
+        .unwrap();
+    assert_eq!(
+        cache.lookup(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        CacheLookup::Value(b"value".to_vec())
+    );
+
+    // a remove forgets the key, the absence has to be recorded again.
+    cache.remove(DEFAULT_PREFIX_NAME, key.clone()).unwrap();
+    assert_eq!(
+        cache.lookup(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        CacheLookup::Miss
+    );
+    let epoch = cache.write_epoch(DEFAULT_PREFIX_NAME, &key);
+    cache
+        .put_absent(DEFAULT_PREFIX_NAME, key.clone(), epoch)
+        .unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(key.clone(), b"batch".to_vec()).unwrap();
+    cache.write_batch(DEFAULT_PREFIX_NAME, batch).unwrap();
+    assert_eq!(
+        cache.lookup(DEFAULT_PREFIX_NAME, key).unwrap(),
+        CacheLookup::Value(b"batch".to_vec())
+    );
+}
+
+#[test]
+fn test_cache_storage_negative_cache_racing_put() {
+    let key = b"raced".to_vec();
+    let cache = CacheStorage::new_with_shards(1, 0, None)
+        .unwrap()
+        .with_negative_cache(true);
+    // a reader misses the key and reads the epoch before querying the backend.
+    let epoch = cache.write_epoch(DEFAULT_PREFIX_NAME, &key);
+    // a writer puts the key before the reader records the miss, then the key is evicted.
+    cache
+        .put(DEFAULT_PREFIX_NAME, key.clone(), b"value".to_vec())
+        .unwrap();
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"other".to_vec(), b"other".to_vec())
+        .unwrap();
+    assert_eq!(cache.get(DEFAULT_PREFIX_NAME, key.clone()).unwrap(), None);
+    // the stale miss is not recorded, the next lookup goes to the backend again.
+    cache
+        .put_absent(DEFAULT_PREFIX_NAME, key.clone(), epoch)
+        .unwrap();
+    assert_eq!(
+        cache.lookup(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        CacheLookup::Miss
+    );
+
+    let epoch = cache.write_epoch(DEFAULT_PREFIX_NAME, &key);
+    cache
+        .put_absent(DEFAULT_PREFIX_NAME, key.clone(), epoch)
+        .unwrap();
+    assert_eq!(
+        cache.lookup(DEFAULT_PREFIX_NAME, key).unwrap(),
+        CacheLookup::Absent
+    );
+}
+
+#[test]
+fn test_cache_storage_scan_prefix() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    for i in (0..10u64).rev() {
+        cache
+            .put(BLOCK_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![i as u8])
+            .unwrap();
+        cache
+            .put(
+                BLOCK_HEADER_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                vec![0u8],
+            )
+            .unwrap();
+    }
+    let entries = cache
+        .scan_prefix(BLOCK_PREFIX_NAME, 5u64.to_be_bytes().to_vec())
+        .unwrap();
+    assert_eq!(
+        entries,
+        (5..10u64)
+            .map(|i| (i.to_be_bytes().to_vec(), vec![i as u8]))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_cache_storage_dump_and_load() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    for i in 0..2u64 {
+        cache
+            .put(
+                DEFAULT_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                vec![i as u8; 8],
+            )
+            .unwrap();
+    }
+    let mut snapshot = vec![];
+    assert_eq!(cache.dump_to(&mut snapshot).unwrap(), 2);
+
+    let restored = CacheStorage::new_with_capacity(1024, None);
+    assert_eq!(restored.load_from(snapshot.as_slice()).unwrap(), 2);
+    for i in 0..2u64 {
+        assert_eq!(
+            restored
+                .get(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec())
+                .unwrap(),
+            Some(vec![i as u8; 8])
+        );
+    }
+
+    // the entry with a mismatched checksum is skipped.
+    let mut corrupted = snapshot.clone();
+    *corrupted.last_mut().unwrap() ^= 0xff;
+    let restored = CacheStorage::new_with_capacity(1024, None);
+    assert_eq!(restored.load_from(corrupted.as_slice()).unwrap(), 1);
+
+    // the truncated entry stops the loading.
+    let truncated = &snapshot[..snapshot.len() - 2];
+    let restored = CacheStorage::new_with_capacity(1024, None);
+    assert_eq!(restored.load_from(truncated).unwrap(), 1);
+
+    let mut unknown_version = snapshot;
+    unknown_version[0] = 0xff;
+    assert!(CacheStorage::new(None)
+        .load_from(unknown_version.as_slice())
+        .is_err());
+}
+
+#[test]
+fn test_cache_storage_clear() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    for i in 0..100u64 {
+        cache
+            .put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8; 8])
+            .unwrap();
+    }
+    cache.clear();
+    assert_eq!(cache.get_len().unwrap(), 0);
+    assert_eq!(cache.byte_len(), 0);
+    assert_eq!(cache.memory_usage(), 0);
+    assert_eq!(
+        cache
+            .get(DEFAULT_PREFIX_NAME, 1u64.to_be_bytes().to_vec())
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_cache_storage_shard_lens() {
+    let cache = CacheStorage::new_with_shards(1024, 2, None).unwrap();
+    for i in 0..100u64 {
+        cache
+            .put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+    }
+    let shard_lens = cache.shard_lens();
+    assert_eq!(shard_lens.len(), 4);
+    assert_eq!(shard_lens.iter().sum::<usize>(), 100);
+
+    let cache = CacheStorage::new(None);
+    assert_eq!(cache.shard_lens().len(), 1 << DEFAULT_SHARD_BITS);
+}
+
+#[test]
+fn test_cache_storage_builder() {
+    let cache = CacheStorage::builder()
+        .capacity(4)
+        .shard_bits(0)
+        .ttl(Some(Duration::from_millis(100)))
+        .build()
+        .unwrap();
+    assert_eq!(cache.shard_lens().len(), 1);
+    for i in 0..8u64 {
+        cache
+            .put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+    }
+    assert_eq!(cache.get_len().unwrap(), 4);
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(
+        cache
+            .get(DEFAULT_PREFIX_NAME, 7u64.to_be_bytes().to_vec())
+            .unwrap(),
+        None
+    );
+
+    assert!(CacheStorage::builder()
+        .shard_bits(MAX_SHARD_BITS + 1)
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_cache_storage_peek() {
+    let cache = CacheStorage::new_with_shards(2, 0, None).unwrap();
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"1".to_vec(), b"v1".to_vec())
+        .unwrap();
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"2".to_vec(), b"v2".to_vec())
+        .unwrap();
+    assert_eq!(
+        cache.peek(DEFAULT_PREFIX_NAME, b"1".to_vec()).unwrap(),
+        Some(b"v1".to_vec())
+    );
+    // peek does not promote the entry, so it is still the least recently used.
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"3".to_vec(), b"v3".to_vec())
+        .unwrap();
+    assert_eq!(
+        cache.peek(DEFAULT_PREFIX_NAME, b"1".to_vec()).unwrap(),
+        None
+    );
+    assert_eq!(
+        cache.peek(DEFAULT_PREFIX_NAME, b"2".to_vec()).unwrap(),
+        Some(b"v2".to_vec())
+    );
+}
+
+#[test]
+fn test_cache_storage_sampled_metrics() {
+    let metrics = StorageMetrics::register(&Registry::new()).unwrap();
+    let cache = CacheStorage::new(Some(metrics.clone()));
+    let calls = METRICS_SAMPLE_RATE * 10;
+    for i in 0..calls {
+        cache
+            .put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8; 8])
+            .unwrap();
+    }
+    // whatever the counter starts from, the consecutive calls contain exactly 10 sampled ones.
+    let sampled_time =
+        metrics
+            .storage_sampled_time
+            .with_label_values(&["cache", DEFAULT_PREFIX_NAME, "put"]);
+    assert_eq!(sampled_time.get_sample_count(), 10);
+    assert_eq!(metrics.cache_items.get(), calls);
+}
+
+#[test]
+fn test_shard_lru_cache_rebuild_with_shards() {
+    let cache = ShardLruCache::with_shards(1024, 4).unwrap();
+    for i in 0..200u64 {
+        cache.put(i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec());
+    }
+    let rebuilt = cache.rebuild_with_shards(6).unwrap();
+    assert_eq!(rebuilt.num_shards(), 64);
+    assert!(cache.is_empty());
+    assert_eq!(cache.entry_count(), 0);
+    assert_eq!(rebuilt.len(), 200);
+    assert_eq!(rebuilt.entry_count(), 200);
+    for i in 0..200u64 {
+        assert_eq!(
+            rebuilt.get(&i.to_be_bytes()),
+            Some(i.to_le_bytes().to_vec())
+        );
+    }
+    assert!(cache.rebuild_with_shards(MAX_SHARD_BITS + 1).is_err());
+
+    // the keys do not spread evenly, but the rebuilt cache never holds more than the budget.
+    let full = ShardLruCache::with_shards(64, 4).unwrap();
+    for i in 0..1000u64 {
+        full.put(i.to_be_bytes().to_vec(), vec![0u8]);
+    }
+    assert_eq!(full.len(), 64);
+    let rebuilt = full.rebuild_with_shards(6).unwrap();
+    assert!(rebuilt.len() <= 64);
+    assert!(rebuilt.shard_lens().iter().all(|len| *len <= 1));
+
+    // a lfu entry keeps its frequency in the rebuilt cache.
+    let lfu = ShardLruCache::with_policy(3, 0, None, EvictionPolicy::Lfu).unwrap();
+    for key in [b"a", b"b", b"c"] {
+        lfu.put(key.to_vec(), key.to_vec());
+    }
+    lfu.get(b"a");
+    lfu.get(b"a");
+    lfu.get(b"b");
+    let rebuilt = lfu.rebuild_with_shards(0).unwrap();
+    assert_eq!(
+        rebuilt
+            .entries_lru_order()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>(),
+        vec![b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]
+    );
+    // c is the least frequently used, then the new d, b and a are used more before the rebuild.
+    rebuilt.put(b"d".to_vec(), b"d".to_vec());
+    rebuilt.put(b"e".to_vec(), b"e".to_vec());
+    assert_eq!(rebuilt.peek(b"c"), None);
+    assert_eq!(rebuilt.peek(b"d"), None);
+    for key in [b"a", b"b", b"e"] {
+        assert_eq!(rebuilt.peek(key), Some(key.to_vec()));
+    }
+}
+
+#[test]
+fn test_cache_storage_prefix_budgets() {
+    let budgets: HashMap<String, usize> = vec![(BLOCK_HEADER_PREFIX_NAME.to_string(), 256)]
+        .into_iter()
+        .collect();
+    let cache = CacheStorage::new_with_prefix_budgets(budgets, 64, None);
+    for i in 0..100u64 {
+        cache
+            .put(
+                BLOCK_HEADER_PREFIX_NAME,
+                i.to_be_bytes().to_vec(),
+                vec![1u8],
+            )
+            .unwrap();
+    }
+    // the noisy prefix only evicts the entries of the shared cache.
+    for i in 0..10000u64 {
+        cache
+            .put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+    }
+    for i in 0..100u64 {
+        assert_eq!(
+            cache
+                .get(BLOCK_HEADER_PREFIX_NAME, i.to_be_bytes().to_vec())
+                .unwrap(),
+            Some(vec![1u8])
+        );
+    }
+    assert_eq!(
+        cache
+            .keys_with_prefix(BLOCK_HEADER_PREFIX_NAME)
+            .unwrap()
+            .len(),
+        100
+    );
+    assert!(cache.keys_with_prefix(DEFAULT_PREFIX_NAME).unwrap().len() <= 64);
+    assert_eq!(
+        cache
+            .get(DEFAULT_PREFIX_NAME, 9999u64.to_be_bytes().to_vec())
+            .unwrap(),
+        Some(vec![0u8])
+    );
+
+    let key = ComposedKey::new(BLOCK_HEADER_PREFIX_NAME, 0u64.to_be_bytes().to_vec());
+    assert_eq!(cache.get_composed(&key), Some(vec![1u8]));
+    assert_eq!(cache.remove_composed(&key), Some(vec![1u8]));
+    cache
+        .remove(BLOCK_HEADER_PREFIX_NAME, 1u64.to_be_bytes().to_vec())
+        .unwrap();
+    assert_eq!(
+        cache
+            .keys_with_prefix(BLOCK_HEADER_PREFIX_NAME)
+            .unwrap()
+            .len(),
+        98
+    );
+    assert_eq!(
+        cache.get_len().unwrap() as usize,
+        98 + cache.keys_with_prefix(DEFAULT_PREFIX_NAME).unwrap().len()
+    );
+}
+
+#[test]
+fn test_cache_storage_prefetch() {
+    let cache = CacheStorage::new(None);
+    cache
+        .put(DEFAULT_PREFIX_NAME, b"cached".to_vec(), b"v0".to_vec())
+        .unwrap();
+    let loaded_keys = Mutex::new(vec![]);
+    let loader = |key: &[u8]| {
+        loaded_keys.lock().unwrap().push(key.to_vec());
+        if key == b"missing" {
+            None
+        } else {
+            Some([key, &b"-loaded"[..]].concat())
+        }
+    };
+    let keys = vec![
+        b"cached".to_vec(),
+        b"k1".to_vec(),
+        b"k2".to_vec(),
+        b"k1".to_vec(),
+        b"missing".to_vec(),
+    ];
+    assert_eq!(cache.prefetch(DEFAULT_PREFIX_NAME, keys.clone(), loader), 2);
+    // the cached key does not call the loader, and the duplicated key is loaded once.
+    let mut loaded = loaded_keys.lock().unwrap().clone();
+    loaded.sort();
+    assert_eq!(
+        loaded,
+        vec![b"k1".to_vec(), b"k2".to_vec(), b"missing".to_vec()]
+    );
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"cached".to_vec()).unwrap(),
+        Some(b"v0".to_vec())
+    );
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"k1".to_vec()).unwrap(),
+        Some(b"k1-loaded".to_vec())
+    );
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"missing".to_vec()).unwrap(),
+        None
+    );
+
+    // only the key absent from the last prefetch is loaded again.
+    loaded_keys.lock().unwrap().clear();
+    assert_eq!(cache.prefetch(DEFAULT_PREFIX_NAME, keys, loader), 0);
+    assert_eq!(*loaded_keys.lock().unwrap(), vec![b"missing".to_vec()]);
+}
+
+#[test]
+fn test_cache_storage_scan_key_prefix() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    for i in 0..4u8 {
+        for j in 0..3u8 {
+            cache
+                .put(BLOCK_PREFIX_NAME, vec![i, j], vec![i * 10 + j])
+                .unwrap();
+        }
+        cache
+            .put(BLOCK_HEADER_PREFIX_NAME, vec![i, 0], vec![0u8])
+            .unwrap();
+    }
+    let mut entries = cache.scan_key_prefix(BLOCK_PREFIX_NAME, vec![2]).unwrap();
+    // the cache does not order the entries.
+    entries.sort();
+    assert_eq!(
+        entries,
+        (0..3u8)
+            .map(|j| (vec![2, j], vec![20 + j]))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        cache
+            .scan_key_prefix(BLOCK_PREFIX_NAME, vec![])
+            .unwrap()
+            .len(),
+        12
+    );
+    assert!(cache
+        .scan_key_prefix(BLOCK_PREFIX_NAME, vec![5])
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_cache_storage_get_len() {
+    let cache = CacheStorage::new_with_capacity(64, None);
+    for i in 0..1000u64 {
+        cache
+            .put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+        if i % 3 == 0 {
+            cache
+                .remove(DEFAULT_PREFIX_NAME, (i / 2).to_be_bytes().to_vec())
+                .unwrap();
+        }
+        if i % 100 == 0 {
+            assert_eq!(cache.get_len().unwrap(), cache.keys().unwrap().len() as u64);
+        }
+    }
+    let mut batch = WriteBatch::new();
+    for i in 990..1010u64 {
+        batch.delete(i.to_be_bytes().to_vec()).unwrap();
+    }
+    cache.write_batch(DEFAULT_PREFIX_NAME, batch).unwrap();
+    assert_eq!(cache.get_len().unwrap(), cache.keys().unwrap().len() as u64);
+    cache.clear();
+    assert_eq!(cache.get_len().unwrap(), 0);
+}
+
+#[test]
+fn test_shard_lru_cache_entries_lru_order() {
+    let cache = ShardLruCache::with_shards(4, 0).unwrap();
+    for i in 0..5u8 {
+        cache.put(vec![i], vec![i]);
+    }
+    cache.get(&[2]);
+    // 0 is evicted, 2 is the most recently used.
+    let keys: Vec<Vec<u8>> = cache
+        .entries_lru_order()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(keys, vec![vec![1], vec![3], vec![4], vec![2]]);
+
+    // only the order within a shard is kept, the shards are merged by rank.
+    let cache = ShardLruCache::with_shards(1024, 4).unwrap();
+    for i in 0..100u64 {
+        cache.put(i.to_be_bytes().to_vec(), vec![0u8]);
+    }
+    let entries = cache.entries_lru_order();
+    assert_eq!(entries.len(), 100);
+    let mut last_positions = vec![None; cache.num_shards()];
+    for (pos, (key, _)) in entries.iter().enumerate() {
+        let shard = cache.shard_index(key);
+        if let Some(last) = last_positions[shard] {
+            // within a shard, the keys are put in increasing order.
+            assert!(entries[last].0 < *key);
+        }
+        last_positions[shard] = Some(pos);
+    }
+    let used_shards = cache.shard_lens().iter().filter(|len| **len > 0).count();
+    let first_shards: std::collections::HashSet<usize> = entries[..used_shards]
+        .iter()
+        .map(|(key, _)| cache.shard_index(key))
+        .collect();
+    assert_eq!(first_shards.len(), used_shards);
+}
+
+#[test]
+fn test_shard_lru_cache_evict_fraction() {
+    let cache = ShardLruCache::with_shards(4, 0).unwrap();
+    for i in 0..4u8 {
+        cache.put(vec![i], vec![i]);
+    }
+    cache.get(&[0]);
+    assert_eq!(cache.evict_fraction(0.0), 0);
+    assert_eq!(cache.evict_fraction(f64::NAN), 0);
+    assert_eq!(cache.len(), 4);
+    // the least recently used entries are evicted first.
+    assert_eq!(cache.evict_fraction(0.5), 2);
+    assert_eq!(cache.keys().len(), 2);
+    assert!(cache.contains(&[0]));
+    assert!(cache.contains(&[3]));
+    assert_eq!(cache.evict_fraction(2.0), 2);
+    assert!(cache.is_empty());
+    assert_eq!(cache.entry_count(), 0);
+}
+
+#[test]
+fn test_cache_storage_on_memory_pressure() {
+    let cache = CacheStorage::new_with_capacity(1024, None);
+    for i in 0..400u64 {
+        cache
+            .put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![0u8])
+            .unwrap();
+    }
+    let evicted = cache.on_memory_pressure();
+    assert!(evicted >= 100);
+    assert_eq!(cache.get_len().unwrap(), 400 - evicted as u64);
+    assert_eq!(cache.keys().unwrap().len(), 400 - evicted);
+}
+
+#[test]
+fn test_cache_storage_put_returning() {
+    let cache = CacheStorage::new(None);
+    assert_eq!(
+        cache
+            .put_returning(DEFAULT_PREFIX_NAME, b"key".to_vec(), b"v1".to_vec())
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        cache
+            .put_returning(DEFAULT_PREFIX_NAME, b"key".to_vec(), b"v2".to_vec())
+            .unwrap(),
+        Some(b"v1".to_vec())
+    );
+    assert_eq!(
+        cache.get(DEFAULT_PREFIX_NAME, b"key".to_vec()).unwrap(),
+        Some(b"v2".to_vec())
+    );
+    assert_eq!(cache.get_len().unwrap(), 1);
+}
@@ -3,9 +3,13 @@
 
 extern crate chrono;
 
-use crate::cache_storage::CacheStorage;
+#[cfg(feature = "async")]
+use crate::async_storage::{AsyncInnerStore, SpawnBlockingStore};
+use crate::batch::WriteBatch;
+use crate::cache_storage::{CacheLookup, CacheStorage};
 use crate::db_storage::DBStorage;
-use crate::storage::{CodecKVStore, InnerStore, StorageInstance, ValueCodec};
+use crate::metrics::StorageMetrics;
+use crate::storage::{get_typed, put_typed, CodecKVStore, InnerStore, StorageInstance, ValueCodec};
 use crate::transaction_info::{BlockTransactionInfo, OldTransactionInfoStorage};
 use crate::{
     BlockInfoStore, BlockStore, BlockTransactionInfoStore, Storage, StorageVersion,
@@ -16,12 +20,14 @@ use anyhow::Result;
 use crypto::HashValue;
 use starcoin_accumulator::accumulator_info::AccumulatorInfo;
 use starcoin_config::RocksdbConfig;
+use starcoin_metrics::Registry;
 use starcoin_types::block::{Block, BlockBody, BlockHeader, BlockInfo};
 use starcoin_types::transaction::{
     RichTransactionInfo, SignedUserTransaction, Transaction, TransactionInfo,
 };
 use starcoin_types::vm_error::KeptVMStatus;
 use std::path::Path;
+use std::sync::Arc;
 
 #[test]
 fn test_reopen() {
@@ -136,6 +142,65 @@ fn test_iter() {
     assert!(iter.next().is_none());
 }
 
+#[test]
+fn test_cache_and_db_negative_cache() {
+    let tmpdir = starcoin_config::temp_dir();
+    let instance = StorageInstance::new_cache_and_db_instance(
+        CacheStorage::new(None).with_negative_cache(true),
+        DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None).unwrap(),
+    );
+    let cache_storage = instance.cache().unwrap();
+    let key = b"absent".to_vec();
+
+    // the miss of the db is recorded, the next get is answered by the cache.
+    assert_eq!(
+        instance.get(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        None
+    );
+    assert_eq!(
+        cache_storage
+            .lookup(DEFAULT_PREFIX_NAME, key.clone())
+            .unwrap(),
+        CacheLookup::Absent
+    );
+    assert_eq!(
+        instance.get(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        None
+    );
+
+    instance
+        .put(DEFAULT_PREFIX_NAME, key.clone(), b"value".to_vec())
+        .unwrap();
+    assert_eq!(
+        instance.get(DEFAULT_PREFIX_NAME, key.clone()).unwrap(),
+        Some(b"value".to_vec())
+    );
+    instance.remove(DEFAULT_PREFIX_NAME, key.clone()).unwrap();
+    assert_eq!(instance.get(DEFAULT_PREFIX_NAME, key).unwrap(), None);
+}
+
+#[test]
+fn test_typed_get_and_put() {
+    let instance = StorageInstance::new_cache_instance();
+    let key = b"typed".to_vec();
+    let value = (7u64, "seven".to_string());
+    put_typed(&instance, DEFAULT_PREFIX_NAME, key.clone(), &value).unwrap();
+    let decoded: Option<(u64, String)> =
+        get_typed(&instance, DEFAULT_PREFIX_NAME, key.clone()).unwrap();
+    assert_eq!(decoded, Some(value));
+    let absent: Option<u64> =
+        get_typed(&instance, DEFAULT_PREFIX_NAME, b"absent".to_vec()).unwrap();
+    assert_eq!(absent, None);
+
+    instance
+        .put(DEFAULT_PREFIX_NAME, key.clone(), vec![1])
+        .unwrap();
+    let err = get_typed::<u64>(&instance, DEFAULT_PREFIX_NAME, key).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains(DEFAULT_PREFIX_NAME));
+    assert!(message.contains(&hex::encode(b"typed")));
+}
+
 #[test]
 fn test_two_level_storage() {
     let tmpdir = starcoin_config::temp_dir();
@@ -248,6 +313,126 @@ fn test_two_level_storage_read_through() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_two_level_storage_multi_get() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let instance = StorageInstance::new_cache_and_db_instance(
+        CacheStorage::new(None),
+        DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None).unwrap(),
+    );
+    let cache_key = HashValue::random().to_vec();
+    let db_key = HashValue::random().to_vec();
+    let missing_key = HashValue::random().to_vec();
+    instance
+        .cache()
+        .unwrap()
+        .put(DEFAULT_PREFIX_NAME, cache_key.clone(), b"cache".to_vec())?;
+    instance
+        .db()
+        .unwrap()
+        .put(DEFAULT_PREFIX_NAME, db_key.clone(), b"db".to_vec())?;
+
+    let values = instance.multi_get(DEFAULT_PREFIX_NAME, vec![missing_key, db_key, cache_key])?;
+    assert_eq!(
+        values,
+        vec![None, Some(b"db".to_vec()), Some(b"cache".to_vec())]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_two_level_storage_multi_contains() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let instance = StorageInstance::new_cache_and_db_instance(
+        CacheStorage::new(None),
+        DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None).unwrap(),
+    );
+    let cache_key = HashValue::random().to_vec();
+    let db_key = HashValue::random().to_vec();
+    let missing_key = HashValue::random().to_vec();
+    instance
+        .cache()
+        .unwrap()
+        .put(DEFAULT_PREFIX_NAME, cache_key.clone(), b"cache".to_vec())?;
+    instance
+        .db()
+        .unwrap()
+        .put(DEFAULT_PREFIX_NAME, db_key.clone(), b"db".to_vec())?;
+
+    let exists =
+        instance.multi_contains(DEFAULT_PREFIX_NAME, vec![missing_key, db_key, cache_key])?;
+    assert_eq!(exists, vec![false, true, true]);
+    Ok(())
+}
+
+#[test]
+fn test_two_level_storage_put_returning() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let instance = StorageInstance::new_cache_and_db_instance(
+        CacheStorage::new(None),
+        DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None).unwrap(),
+    );
+    let key = HashValue::random().to_vec();
+    assert_eq!(
+        instance.put_returning(DEFAULT_PREFIX_NAME, key.clone(), b"v1".to_vec())?,
+        None
+    );
+    // not in the cache, the previous value is read from the db.
+    instance.cache().unwrap().clear();
+    assert_eq!(
+        instance.put_returning(DEFAULT_PREFIX_NAME, key.clone(), b"v2".to_vec())?,
+        Some(b"v1".to_vec())
+    );
+    assert_eq!(
+        instance
+            .db()
+            .unwrap()
+            .get(DEFAULT_PREFIX_NAME, key.clone())?,
+        Some(b"v2".to_vec())
+    );
+    assert_eq!(
+        instance.cache().unwrap().get(DEFAULT_PREFIX_NAME, key)?,
+        Some(b"v2".to_vec())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_two_level_storage_put_if_absent() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let instance = StorageInstance::new_cache_and_db_instance(
+        CacheStorage::new(None),
+        DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None).unwrap(),
+    );
+    let key = HashValue::random().to_vec();
+    assert!(instance.put_if_absent(DEFAULT_PREFIX_NAME, key.clone(), b"v1".to_vec())?);
+    assert!(!instance.put_if_absent(DEFAULT_PREFIX_NAME, key.clone(), b"v2".to_vec())?);
+    assert_eq!(
+        instance
+            .db()
+            .unwrap()
+            .get(DEFAULT_PREFIX_NAME, key.clone())?,
+        Some(b"v1".to_vec())
+    );
+    assert_eq!(
+        instance.cache().unwrap().get(DEFAULT_PREFIX_NAME, key)?,
+        Some(b"v1".to_vec())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_db_storage_compare_and_swap() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let db = DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None)?;
+    let key = HashValue::random().to_vec();
+    assert!(db.compare_and_swap(DEFAULT_PREFIX_NAME, key.clone(), None, Some(b"v1".to_vec()))?);
+    assert!(!db.compare_and_swap(DEFAULT_PREFIX_NAME, key.clone(), Some(b"v2".to_vec()), None)?);
+    assert!(db.compare_and_swap(DEFAULT_PREFIX_NAME, key.clone(), Some(b"v1".to_vec()), None)?);
+    assert_eq!(db.get(DEFAULT_PREFIX_NAME, key)?, None);
+    Ok(())
+}
+
 #[test]
 fn test_missing_key_handle() -> Result<()> {
     let tmpdir = starcoin_config::temp_dir();
@@ -365,3 +550,130 @@ pub fn test_db_upgrade() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn test_scan_prefix() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let instance = StorageInstance::new_cache_and_db_instance(
+        CacheStorage::new_with_capacity(2, None),
+        DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None)?,
+    );
+    for i in (0..10u64).rev() {
+        instance.put(DEFAULT_PREFIX_NAME, i.to_be_bytes().to_vec(), vec![i as u8])?;
+    }
+    // the db is scanned, the entries evicted from the cache are included.
+    let entries = instance.scan_prefix(DEFAULT_PREFIX_NAME, 5u64.to_be_bytes().to_vec())?;
+    assert_eq!(
+        entries,
+        (5..10u64)
+            .map(|i| (i.to_be_bytes().to_vec(), vec![i as u8]))
+            .collect::<Vec<_>>()
+    );
+    Ok(())
+}
+
+#[stest::test]
+fn test_scan_key_prefix() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let instance = StorageInstance::new_cache_and_db_instance(
+        CacheStorage::new_with_capacity(2, None),
+        DBStorage::new(tmpdir.path(), RocksdbConfig::default(), None)?,
+    );
+    for i in (0..4u8).rev() {
+        for j in 0..3u8 {
+            instance.put(DEFAULT_PREFIX_NAME, vec![i, j], vec![i * 10 + j])?;
+        }
+    }
+    // the db returns the entries ordered by key, including the ones evicted from the cache.
+    let entries = instance.scan_key_prefix(DEFAULT_PREFIX_NAME, vec![1])?;
+    assert_eq!(
+        entries,
+        (0..3u8)
+            .map(|j| (vec![1, j], vec![10 + j]))
+            .collect::<Vec<_>>()
+    );
+    assert!(instance
+        .scan_key_prefix(DEFAULT_PREFIX_NAME, vec![4])?
+        .is_empty());
+    Ok(())
+}
+
+#[stest::test]
+fn test_cache_and_db_sync_write() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let metrics = StorageMetrics::register(&Registry::new())?;
+    let instance = StorageInstance::new_cache_and_db_instance(
+        CacheStorage::new(None),
+        DBStorage::new(
+            tmpdir.path(),
+            RocksdbConfig::default(),
+            Some(metrics.clone()),
+        )?,
+    );
+    let db_writes = |method: &str| {
+        metrics
+            .storage_rw_total
+            .with_label_values(&["db", DEFAULT_PREFIX_NAME, method, "ok"])
+            .get()
+    };
+    let mut batch = WriteBatch::new();
+    batch.put(b"k1".to_vec(), b"v1".to_vec())?;
+    instance.write_batch_sync(DEFAULT_PREFIX_NAME, batch)?;
+    instance.put_sync(DEFAULT_PREFIX_NAME, b"k2".to_vec(), b"v2".to_vec())?;
+    // the sync is forwarded to the db, not downgraded to the plain write.
+    assert_eq!(db_writes("write_batch_sync"), 1);
+    assert_eq!(db_writes("put_sync"), 1);
+    assert_eq!(db_writes("write_batch"), 0);
+    assert_eq!(db_writes("put"), 0);
+
+    instance.put(DEFAULT_PREFIX_NAME, b"k3".to_vec(), b"v3".to_vec())?;
+    assert_eq!(db_writes("put"), 1);
+    assert_eq!(db_writes("put_sync"), 1);
+    for (key, value) in [(b"k1", b"v1"), (b"k2", b"v2"), (b"k3", b"v3")] {
+        let db = instance.db().unwrap();
+        assert_eq!(
+            db.get(DEFAULT_PREFIX_NAME, key.to_vec())?,
+            Some(value.to_vec())
+        );
+        let cache = instance.cache().unwrap();
+        assert_eq!(
+            cache.get(DEFAULT_PREFIX_NAME, key.to_vec())?,
+            Some(value.to_vec())
+        );
+    }
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+#[stest::test]
+async fn test_spawn_blocking_store() -> Result<()> {
+    let tmpdir = starcoin_config::temp_dir();
+    let db = Arc::new(DBStorage::new(
+        tmpdir.path(),
+        RocksdbConfig::default(),
+        None,
+    )?);
+    let store = SpawnBlockingStore::new(db.clone());
+    let key = HashValue::random().to_vec();
+    store
+        .put(DEFAULT_PREFIX_NAME, key.clone(), b"v".to_vec())
+        .await?;
+    assert_eq!(
+        AsyncInnerStore::get(&store, DEFAULT_PREFIX_NAME, key.clone()).await?,
+        Some(b"v".to_vec())
+    );
+    assert_eq!(
+        InnerStore::get(db.as_ref(), DEFAULT_PREFIX_NAME, key.clone())?,
+        Some(b"v".to_vec())
+    );
+    AsyncInnerStore::remove(&store, DEFAULT_PREFIX_NAME, key.clone()).await?;
+    assert!(!AsyncInnerStore::contains_key(&store, DEFAULT_PREFIX_NAME, key).await?);
+
+    let cache = CacheStorage::new(None);
+    AsyncInnerStore::put(&cache, DEFAULT_PREFIX_NAME, b"k".to_vec(), b"v".to_vec()).await?;
+    assert_eq!(
+        AsyncInnerStore::get(&cache, DEFAULT_PREFIX_NAME, b"k".to_vec()).await?,
+        Some(b"v".to_vec())
+    );
+    Ok(())
+}
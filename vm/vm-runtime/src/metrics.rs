@@ -11,6 +11,7 @@ pub struct VMMetrics {
     pub vm_txn_exe_total: UIntCounterVec,
     pub vm_txn_exe_time: HistogramVec,
     pub vm_txn_gas_usage: Histogram,
+    pub vm_lock_wait_time: HistogramVec,
 }
 
 impl VMMetrics {
@@ -36,10 +37,18 @@ impl VMMetrics {
             ))?,
             registry,
         )?;
+        let vm_lock_wait_time = register(
+            HistogramVec::new(
+                HistogramOpts::new("vm_lock_wait_time", "wait time to lock the shared vm"),
+                &["method"],
+            )?,
+            registry,
+        )?;
         Ok(Self {
             vm_txn_exe_total,
             vm_txn_exe_time,
             vm_txn_gas_usage,
+            vm_lock_wait_time,
         })
     }
 }
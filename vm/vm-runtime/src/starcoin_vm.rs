@@ -66,6 +66,7 @@ use starcoin_vm_types::{
 };
 use std::convert::{TryFrom, TryInto};
 use std::sync::Arc;
+use std::time::Instant;
 
 static ZERO_COST_SCHEDULE: Lazy<CostTable> =
     Lazy::new(|| zero_cost_schedule(NativeCostIndex::NUMBER_OF_NATIVE_FUNCTIONS));
@@ -79,34 +80,76 @@ pub struct StarcoinVM {
     version: Option<Version>,
     move_version: Option<MoveLanguageVersion>,
     metrics: Option<VMMetrics>,
+    /// Used instead of the on-chain gas schedule if set.
+    gas_schedule_override: Option<CostTable>,
 }
 
 /// marking of stdlib version which includes vmconfig upgrades.
 const VMCONFIG_UPGRADE_VERSION_MARK: u64 = 10;
+/// marking of stdlib version from which a block flushes the module cache once a txn publishes modules.
+const MODULE_CACHE_FLUSH_VERSION_MARK: u64 = 12;
 
 impl StarcoinVM {
     pub fn new(metrics: Option<VMMetrics>) -> Self {
-        let inner = MoveVM::new(super::natives::starcoin_natives())
-            .expect("should be able to create Move VM; check if there are duplicated natives");
         Self {
-            move_vm: Arc::new(inner),
+            move_vm: Arc::new(Self::new_move_vm()),
             vm_config: None,
             version: None,
             move_version: None,
             metrics,
+            gas_schedule_override: None,
         }
     }
 
+    pub fn metrics(&self) -> Option<&VMMetrics> {
+        self.metrics.as_ref()
+    }
+
+    fn new_move_vm() -> MoveVM {
+        MoveVM::new(super::natives::starcoin_natives())
+            .expect("should be able to create Move VM; check if there are duplicated natives")
+    }
+
+    /// Drop the modules cached by the vm, so the later executions load the modules from the state,
+    /// e.g. after the modules are upgraded by a txn not executed by this vm.
+    /// The loaded configs are kept. The block execution flushes the cache itself
+    /// once a txn of the block publishes modules, see [`Self::flushes_module_cache_on_publish`].
+    pub fn flush_module_cache(&mut self) {
+        self.move_vm = Arc::new(Self::new_move_vm());
+    }
+
+    /// Whether the block execution flushes the module cache after a txn publishes modules,
+    /// so the later txns of the block run the new code. Before the stdlib version
+    /// `MODULE_CACHE_FLUSH_VERSION_MARK`, the later txns run the modules cached before the
+    /// upgrade, and replaying the blocks of those versions must give the same outputs.
+    pub fn flushes_module_cache_on_publish(&self) -> bool {
+        self.version.as_ref().map_or(false, |version| {
+            version.clone().into_stdlib_version()
+                >= StdlibVersion::Version(MODULE_CACHE_FLUSH_VERSION_MARK)
+        })
+    }
+
+    /// Override the on-chain gas schedule with `gas_schedule` when loading configs,
+    /// `None` restores the on-chain gas schedule.
+    pub fn set_gas_schedule_override(&mut self, gas_schedule: Option<CostTable>) {
+        self.gas_schedule_override = gas_schedule;
+    }
+
     pub fn load_configs(&mut self, state: &dyn StateView) -> Result<(), Error> {
         if state.is_genesis() {
             self.vm_config = Some(VMConfig {
                 gas_schedule: LATEST_GAS_SCHEDULE.clone(),
             });
             self.version = Some(Version { major: 1 });
-            Ok(())
         } else {
-            self.load_configs_impl(state)
+            self.load_configs_impl(state)?;
+        }
+        if let Some(gas_schedule) = &self.gas_schedule_override {
+            self.vm_config = Some(VMConfig {
+                gas_schedule: gas_schedule.clone(),
+            });
         }
+        Ok(())
     }
 
     fn load_configs_impl(&mut self, state: &dyn StateView) -> Result<(), Error> {
@@ -989,8 +1032,28 @@ impl StarcoinVM {
         transactions: Vec<Transaction>,
         block_gas_limit: Option<u64>,
     ) -> Result<Vec<(VMStatus, TransactionOutput)>> {
+        self.execute_block_transactions_with_deadline(
+            state_view,
+            transactions,
+            block_gas_limit,
+            None,
+        )
+        .map(|(result, _)| result)
+    }
+
+    /// Same as `execute_block_transactions`, but stop before executing the next user transaction
+    /// once the `deadline` passed, like the block gas limit is used up.
+    /// Also return whether the execution is stopped by the deadline.
+    pub fn execute_block_transactions_with_deadline(
+        &mut self,
+        state_view: &dyn StateView,
+        transactions: Vec<Transaction>,
+        block_gas_limit: Option<u64>,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<(VMStatus, TransactionOutput)>, bool)> {
         let mut data_cache = StateViewCache::new(state_view);
         let mut result = vec![];
+        let mut deadline_exceeded = false;
         //TODO load config by config change event.
         self.load_configs(&data_cache)?;
 
@@ -1002,6 +1065,12 @@ impl StarcoinVM {
             match block {
                 TransactionBlock::UserTransaction(txns) => {
                     for transaction in txns {
+                        if let Some(deadline) = deadline {
+                            if Instant::now() >= deadline {
+                                deadline_exceeded = true;
+                                break 'outer;
+                            }
+                        }
                         let timer = self.metrics.as_ref().map(|metrics| {
                             metrics
                                 .vm_txn_exe_time
@@ -1025,7 +1094,16 @@ impl StarcoinVM {
                                     "Keep transaction gas used must not be zero"
                                 );
                             }
-                            data_cache.push_write_set(output.write_set())
+                            data_cache.push_write_set(output.write_set());
+                            // the later txns must not run the modules cached before the upgrade.
+                            if self.flushes_module_cache_on_publish()
+                                && output
+                                    .write_set()
+                                    .iter()
+                                    .any(|(access_path, _)| access_path.path.is_code())
+                            {
+                                self.flush_module_cache();
+                            }
                         }
                         self.check_reconfigure(&data_cache, &output)?;
                         if let Some(timer) = timer {
@@ -1085,7 +1163,7 @@ impl StarcoinVM {
                 }
             }
         }
-        Ok(result)
+        Ok((result, deadline_exceeded))
     }
 
     pub fn execute_readonly_function(
@@ -1096,6 +1174,25 @@ impl StarcoinVM {
         type_params: Vec<TypeTag>,
         args: Vec<Vec<u8>>,
     ) -> Result<Vec<Vec<u8>>, VMStatus> {
+        self.execute_readonly_function_with_events(
+            state_view,
+            module,
+            function_name,
+            type_params,
+            args,
+        )
+        .map(|(result, _events)| result)
+    }
+
+    /// Same as `execute_readonly_function`, but also return the events emitted by the function.
+    pub fn execute_readonly_function_with_events(
+        &mut self,
+        state_view: &dyn StateView,
+        module: &ModuleId,
+        function_name: &IdentStr,
+        type_params: Vec<TypeTag>,
+        args: Vec<Vec<u8>>,
+    ) -> Result<(Vec<Vec<u8>>, Vec<ContractEvent>), VMStatus> {
         let _timer = self.metrics.as_ref().map(|metrics| {
             metrics
                 .vm_txn_exe_time
@@ -1116,12 +1213,12 @@ impl StarcoinVM {
             .map_err(|e| e.into_vm_status())?;
 
         let (changeset, events) = session.finish().map_err(|e| e.into_vm_status())?;
-        let (writeset, _events) = convert_changeset_and_events(changeset, events)?;
+        let (writeset, events) = convert_changeset_and_events(changeset, events)?;
         if !writeset.is_empty() {
             warn!("Readonly function {} changes state", function_name);
             return Err(VMStatus::Error(StatusCode::REJECTED_WRITE_SET));
         }
-        Ok(result)
+        Ok((result, events))
     }
 
     fn success_transaction_cleanup<R: MoveResolver>(